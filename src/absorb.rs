@@ -0,0 +1,448 @@
+use crate::report;
+use colored::Colorize;
+use git2::{BlameOptions, Delta, Diff, DiffOptions, Index, Oid, Repository, Tree};
+use gx::checkout::has_uncommitted_changes;
+use gx::error::GxError;
+use gx::restack;
+use gx::stack::Stack;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// A staged hunk, identified by its position in the diff from `HEAD` to the
+/// index.
+#[derive(Debug)]
+struct Hunk {
+    path: PathBuf,
+    old_start: u32,
+    old_lines: u32,
+    new_start: u32,
+    /// Lines the hunk adds, with their line endings.
+    added: Vec<Vec<u8>>,
+}
+
+/// The commit a hunk is absorbed into, or why it stays staged.
+type Placement = Result<Oid, String>;
+
+impl Hunk {
+    fn location(&self) -> String {
+        let line = if self.old_lines == 0 {
+            self.new_start
+        } else {
+            self.old_start
+        };
+        format!("{}:{line}", self.path.display())
+    }
+}
+
+/// Folds every staged hunk into the commit of the current branch or one of the
+/// branches below it that last touched the lines it changes, then restacks the
+/// other branches stacked on the rewritten ones.
+///
+/// Lines are blamed back to the merge-base with the trunk. A hunk is absorbed
+/// only when all the lines it changes, or the lines around an insertion, come
+/// from a single stack commit. Other hunks stay staged and are reported.
+pub fn absorb(repo: &Repository, trunk_name: Option<&str>) -> Result<(), GxError> {
+    restack::ensure_no_operation(repo)?;
+    let (mut stack, head_branch) = Stack::current(repo, trunk_name, "to absorb changes")?;
+    let head_commit = repo.head()?.peel_to_commit()?;
+
+    // The current branch and every branch below it, bottom first.
+    let mut chain = vec![head_branch.clone()];
+    while let Some(parent) = stack.find(chain.last().unwrap()).map(|b| b.parent.clone()) {
+        if stack.find(&parent).is_none() {
+            break;
+        }
+        chain.push(parent);
+    }
+    chain.reverse();
+
+    let names: Vec<&str> = chain.iter().map(String::as_str).collect();
+    refuse_merges(repo, &stack, &names)?;
+
+    // Commits absorb may amend, oldest first, with the branch each is on.
+    let mut commits = Vec::new();
+    for name in &chain {
+        let b = stack.find(name).unwrap();
+        for oid in stack.commits(repo, b)?.into_iter().rev() {
+            commits.push((oid, name.clone()));
+        }
+    }
+    let owned: HashSet<Oid> = commits.iter().map(|(oid, _)| *oid).collect();
+
+    let head_tree = head_commit.tree()?;
+    let index = repo.index()?;
+    let staged = repo.diff_tree_to_index(
+        Some(&head_tree),
+        Some(&index),
+        Some(DiffOptions::new().context_lines(0)),
+    )?;
+    if staged.deltas().len() == 0 {
+        return Err(GxError::InvalidState(
+            "No staged changes to absorb. Stage changes with `git add` first.".to_string(),
+        ));
+    }
+
+    let boundary = repo.merge_base(head_commit.id(), stack.trunk.oid)?;
+    let mut targets: HashMap<Oid, Vec<Hunk>> = HashMap::new();
+    let mut left: Vec<(Hunk, String)> = Vec::new();
+    for (hunk, target) in blame_hunks(repo, &staged, head_commit.id(), boundary, &owned)? {
+        match target {
+            Ok(oid) => targets.entry(oid).or_default().push(hunk),
+            Err(reason) => left.push((hunk, reason)),
+        }
+    }
+
+    // Every chain branch from the one holding the oldest target up is
+    // rewritten, so the other branches stacked on them move too, along with
+    // everything above those.
+    let first_rewritten = commits
+        .iter()
+        .find(|(oid, _)| targets.contains_key(oid))
+        .and_then(|(_, name)| chain.iter().position(|n| n == name))
+        .unwrap_or(chain.len());
+    let mut moving: HashSet<&str> = chain[first_rewritten..]
+        .iter()
+        .map(String::as_str)
+        .collect();
+    let mut pending = Vec::new();
+    for b in &stack.branches {
+        if !chain.contains(&b.name) && moving.contains(b.parent.as_str()) {
+            moving.insert(&b.name);
+            pending.push(b.name.clone());
+        }
+    }
+    let names: Vec<&str> = pending.iter().map(String::as_str).collect();
+    refuse_merges(repo, &stack, &names)?;
+    if !pending.is_empty()
+        && repo
+            .diff_index_to_workdir(Some(&index), None)?
+            .deltas()
+            .len()
+            > 0
+    {
+        return Err(GxError::InvalidState(
+            "You have unstaged changes, which restacking the branches stacked on the rewritten ones would overwrite. Stage or stash them first.".to_string(),
+        ));
+    }
+
+    // Rewrite every commit from the oldest target up. Blame guarantees that
+    // the lines a hunk touches are the same in each of those commits as in
+    // HEAD, so the hunk is placed in each commit's own version of the file
+    // instead of merging, which would trip over neighbouring changes.
+    let signature = repo.signature()?;
+    let mut mapping: HashMap<Oid, Oid> = HashMap::new();
+    let mut active: Vec<&Hunk> = Vec::new();
+    let mut absorbed = Vec::new();
+    for (oid, name) in &commits {
+        if let Some(hunks) = targets.get(oid) {
+            active.extend(hunks);
+            absorbed.push((*oid, name.clone(), hunks.len()));
+        }
+        if active.is_empty() {
+            continue;
+        }
+        let commit = repo.find_commit(*oid)?;
+        let parent = commit.parent(0)?;
+        let new_parent = match mapping.get(&parent.id()) {
+            Some(&p) => repo.find_commit(p)?,
+            None => parent,
+        };
+        let tree = apply_hunks(repo, &head_tree, &commit.tree()?, &active)?;
+        let new_oid = repo.commit(
+            None,
+            &commit.author(),
+            &signature,
+            commit.message_raw().unwrap_or_default(),
+            &tree,
+            &[&new_parent],
+        )?;
+        mapping.insert(*oid, new_oid);
+    }
+
+    for (oid, name, count) in &absorbed {
+        let commit = repo.find_commit(*oid)?;
+        println!(
+            "{} {count} {} into {} {} ({})",
+            "Absorbed".green().bold(),
+            if *count == 1 { "hunk" } else { "hunks" },
+            oid.to_string()[0..7].red().bold(),
+            commit.summary().unwrap_or("<no summary>"),
+            name.yellow().bold(),
+        );
+    }
+    left.sort_by(|(a, _), (b, _)| (&a.path, a.new_start).cmp(&(&b.path, b.new_start)));
+    for (hunk, reason) in &left {
+        println!(
+            "{} {} ({reason})",
+            "Left staged".yellow().bold(),
+            hunk.location()
+        );
+    }
+    if absorbed.is_empty() {
+        return Err(GxError::InvalidState(
+            "None of the staged changes could be absorbed.".to_string(),
+        ));
+    }
+
+    // Move the branches to their rewritten commits. The index is left alone,
+    // so whatever was not absorbed stays staged.
+    for name in &chain {
+        let b = stack.find(name).unwrap();
+        let tip = mapping.get(&b.tip).copied().unwrap_or(b.tip);
+        let base = mapping.get(&b.base).copied().unwrap_or(b.base);
+        if tip != b.tip {
+            repo.reference(&format!("refs/heads/{name}"), tip, true, "gx absorb")?;
+        }
+        for sb in stack.branches.iter_mut() {
+            if sb.name == *name {
+                sb.tip = tip;
+                sb.base = base;
+            }
+        }
+    }
+
+    // Recording the stack's shape keeps the rewritten branches' bases current
+    // and the branches stacked on them attached, even when those cannot be
+    // restacked right away.
+    if pending.is_empty() {
+        return restack::record_stack(repo, &stack);
+    }
+    let op = restack::start_operation(repo, "absorb", &stack, &head_branch, pending)?;
+    if has_uncommitted_changes(repo)? {
+        println!(
+            "{} The branches stacked on the rewritten ones were not restacked because some changes are still staged. Commit or stash them and run `gx stack restack`.",
+            "Note:".yellow().bold(),
+        );
+        return Ok(());
+    }
+    report::restack(restack::run_operation(repo, op)?)
+}
+
+/// Refuses to rewrite `names` if any of them contains a merge commit.
+fn refuse_merges(repo: &Repository, stack: &Stack, names: &[&str]) -> Result<(), GxError> {
+    match restack::find_merge(repo, stack, names)? {
+        Some((name, oid)) => Err(GxError::UnsupportedHistory(format!(
+            "Branch {} contains merge commit {}, which absorb cannot rewrite.",
+            name.yellow().bold(),
+            &oid.to_string()[0..7],
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Finds the stack commit each hunk of `diff` belongs to, or the reason it
+/// cannot be absorbed.
+fn blame_hunks(
+    repo: &Repository,
+    diff: &Diff,
+    head: Oid,
+    boundary: Oid,
+    owned: &HashSet<Oid>,
+) -> Result<Vec<(Hunk, Placement)>, GxError> {
+    let mut result = Vec::new();
+    for (i, delta) in diff.deltas().enumerate() {
+        let Some(path) = delta.new_file().path().map(Path::to_path_buf) else {
+            continue;
+        };
+        let Some(patch) = git2::Patch::from_diff(diff, i)? else {
+            continue;
+        };
+        let mut hunks = Vec::new();
+        for h in 0..patch.num_hunks() {
+            let (hunk, line_count) = patch.hunk(h)?;
+            let mut added = Vec::new();
+            for l in 0..line_count {
+                let line = patch.line_in_hunk(h, l)?;
+                if line.origin() == '+' {
+                    added.push(line.content().to_vec());
+                }
+            }
+            hunks.push(Hunk {
+                path: path.clone(),
+                old_start: hunk.old_start(),
+                old_lines: hunk.old_lines(),
+                new_start: hunk.new_start(),
+                added,
+            });
+        }
+        if hunks.is_empty() {
+            // Binary files and mode changes have no hunks to place.
+            let hunk = Hunk {
+                path,
+                old_start: 0,
+                old_lines: 0,
+                new_start: 0,
+                added: Vec::new(),
+            };
+            result.push((hunk, Err("no text changes".to_string())));
+            continue;
+        }
+        if delta.status() != Delta::Modified {
+            let reason = match delta.status() {
+                Delta::Added => "new file",
+                Delta::Deleted => "deleted file",
+                _ => "file was renamed or copied",
+            };
+            for hunk in hunks {
+                result.push((hunk, Err(reason.to_string())));
+            }
+            continue;
+        }
+
+        let blame = repo.blame_file(
+            &path,
+            Some(
+                BlameOptions::new()
+                    .newest_commit(head)
+                    .oldest_commit(boundary)
+                    .first_parent(true),
+            ),
+        )?;
+        for hunk in hunks {
+            // Changed lines are blamed directly. An insertion has none, so
+            // the lines right above and below it decide instead.
+            let lines: Vec<usize> = if hunk.old_lines == 0 {
+                vec![hunk.old_start as usize, hunk.old_start as usize + 1]
+            } else {
+                (hunk.old_start..hunk.old_start + hunk.old_lines)
+                    .map(|l| l as usize)
+                    .collect()
+            };
+            let found: HashSet<Oid> = lines
+                .into_iter()
+                .filter(|&l| l > 0)
+                .filter_map(|l| blame.get_line(l).map(|b| b.final_commit_id()))
+                .collect();
+
+            let target = if found.is_empty() {
+                Err("no surrounding lines to blame".to_string())
+            } else if found.iter().any(|oid| !owned.contains(oid)) {
+                Err("changes lines from outside the stack".to_string())
+            } else if found.len() > 1 {
+                Err("changes lines from several commits".to_string())
+            } else {
+                Ok(*found.iter().next().unwrap())
+            };
+            result.push((hunk, target));
+        }
+    }
+    Ok(result)
+}
+
+/// Applies `hunks`, which were made against `head_tree`, to `tree`. Every line
+/// a hunk replaces or inserts next to must be unchanged between the two.
+fn apply_hunks<'r>(
+    repo: &'r Repository,
+    head_tree: &Tree,
+    tree: &Tree,
+    hunks: &[&Hunk],
+) -> Result<Tree<'r>, GxError> {
+    let mut by_path: HashMap<&Path, Vec<&Hunk>> = HashMap::new();
+    for hunk in hunks {
+        by_path.entry(hunk.path.as_path()).or_default().push(hunk);
+    }
+
+    let mut index = Index::new()?;
+    index.read_tree(tree)?;
+    for (path, mut hunks) in by_path {
+        let head_blob = repo.find_blob(head_tree.get_path(path)?.id())?;
+        let blob = repo.find_blob(tree.get_path(path)?.id())?;
+        let from_head = LineMap::new(blob.content(), head_blob.content())?;
+        hunks.sort_by_key(|h| h.old_start);
+
+        // Lines to drop, and lines to insert before each line index.
+        let mut removed = HashSet::new();
+        let mut inserted: HashMap<usize, Vec<&[u8]>> = HashMap::new();
+        for hunk in hunks {
+            let position = if hunk.old_lines == 0 {
+                match from_head.line(hunk.old_start) {
+                    Some(above) => above,
+                    None => from_head
+                        .line(hunk.old_start + 1)
+                        .map_or(0, |below| below - 1),
+                }
+            } else {
+                let mut first = None;
+                for l in hunk.old_start..hunk.old_start + hunk.old_lines {
+                    let line = from_head.line(l).ok_or_else(|| {
+                        GxError::Other(format!("Could not place {}.", hunk.location()))
+                    })?;
+                    first.get_or_insert(line - 1);
+                    removed.insert(line - 1);
+                }
+                first.unwrap_or_default()
+            };
+            inserted
+                .entry(position)
+                .or_default()
+                .extend(hunk.added.iter().map(Vec::as_slice));
+        }
+
+        let lines: Vec<&[u8]> = blob.content().split_inclusive(|&b| b == b'\n').collect();
+        let mut content = Vec::new();
+        for i in 0..=lines.len() {
+            for added in inserted.get(&i).into_iter().flatten() {
+                content.extend_from_slice(added);
+            }
+            if i < lines.len() && !removed.contains(&i) {
+                content.extend_from_slice(lines[i]);
+            }
+        }
+        let mut entry = index
+            .get_path(path, 0)
+            .ok_or_else(|| GxError::Other(format!("{} is not in the tree.", path.display())))?;
+        entry.id = repo.blob(&content)?;
+        entry.file_size = content.len() as u32;
+        index.add(&entry)?;
+    }
+    Ok(repo.find_tree(index.write_tree_to(repo)?)?)
+}
+
+/// Maps lines of one version of a file to another, for lines the two share.
+struct LineMap {
+    /// Hunks of the diff from the old version to the new one, as
+    /// `(old_lines, new_start, new_lines)`.
+    hunks: Vec<(u32, u32, u32)>,
+}
+
+impl LineMap {
+    fn new(old: &[u8], new: &[u8]) -> Result<LineMap, git2::Error> {
+        let patch = git2::Patch::from_buffers(
+            old,
+            None,
+            new,
+            None,
+            Some(DiffOptions::new().context_lines(0)),
+        )?;
+        let mut hunks = Vec::new();
+        for h in 0..patch.num_hunks() {
+            let (hunk, _) = patch.hunk(h)?;
+            hunks.push((hunk.old_lines(), hunk.new_start(), hunk.new_lines()));
+        }
+        Ok(LineMap { hunks })
+    }
+
+    /// The old line number of the new version's line `line`, counting from 1,
+    /// or `None` if the line does not exist in the old version.
+    fn line(&self, line: u32) -> Option<usize> {
+        if line == 0 {
+            return None;
+        }
+        let mut offset: i64 = 0;
+        for &(old_lines, new_start, new_lines) in &self.hunks {
+            // A hunk with no new lines sits right after line `new_start`.
+            let last = if new_lines == 0 {
+                new_start
+            } else {
+                if (new_start..new_start + new_lines).contains(&line) {
+                    return None;
+                }
+                new_start + new_lines - 1
+            };
+            if line > last {
+                offset += i64::from(old_lines) - i64::from(new_lines);
+            }
+        }
+        Some((i64::from(line) + offset) as usize)
+    }
+}
@@ -0,0 +1,108 @@
+use crate::error::GxError;
+use crate::meta::{self, BranchMeta};
+use crate::stack::Stack;
+use crate::trunk::Trunk;
+use git2::{BranchType, Repository};
+
+/// Creates branch `name` at the tip of the checked out branch `parent`,
+/// records it as stacked on `parent` and checks it out. With a `message`, the
+/// staged changes are committed to the new branch.
+pub fn create(
+    repo: &Repository,
+    trunk: &Trunk,
+    parent: &str,
+    name: &str,
+    message: Option<&str>,
+) -> Result<(), GxError> {
+    let head_commit = repo.head()?.peel_to_commit()?;
+    if message.is_some() {
+        let staged = repo.diff_tree_to_index(Some(&head_commit.tree()?), None, None)?;
+        if staged.deltas().len() == 0 {
+            return Err(GxError::InvalidState(
+                "No staged changes to commit. Stage changes with `git add` first.".to_string(),
+            ));
+        }
+    }
+
+    // Record the parent under the trunk's configured name so the stack model
+    // recognizes the bottom branch even when the trunk is a remote branch.
+    let recorded_parent = if trunk.name.ends_with(&format!("/{parent}")) {
+        trunk.name.clone()
+    } else {
+        parent.to_string()
+    };
+
+    let branch = repo.branch(name, &head_commit, false)?;
+    let branch_ref = branch
+        .get()
+        .name()
+        .ok_or("Branch name is not valid UTF-8.")?;
+    repo.set_head(branch_ref)?;
+    meta::write(
+        repo,
+        name,
+        &BranchMeta {
+            parent: recorded_parent,
+            base: head_commit.id(),
+        },
+    )?;
+
+    if let Some(message) = message {
+        let signature = repo.signature()?;
+        let mut index = repo.index()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &[&head_commit],
+        )?;
+    }
+    Ok(())
+}
+
+/// Records `branch` as stacked on `parent`, or on the parent inferred from
+/// history, and returns what was recorded.
+pub fn track(
+    repo: &Repository,
+    trunk: &Trunk,
+    branch: &str,
+    parent: Option<&str>,
+) -> Result<BranchMeta, GxError> {
+    if branch == trunk.name {
+        return Err(GxError::InvalidState(format!(
+            "The trunk branch {} cannot be tracked.",
+            trunk.name
+        )));
+    }
+    let tip = repo
+        .find_branch(branch, BranchType::Local)?
+        .get()
+        .peel_to_commit()?
+        .id();
+
+    let parent = match parent {
+        Some(p) => p.to_string(),
+        None => match Stack::for_branch(repo, trunk.clone(), branch)?
+            .and_then(|s| s.find(branch).map(|b| b.parent.clone()))
+        {
+            Some(p) => p,
+            None => trunk.name.clone(),
+        },
+    };
+    let parent_tip = if parent == trunk.name {
+        trunk.oid
+    } else {
+        repo.find_branch(&parent, BranchType::Local)?
+            .get()
+            .peel_to_commit()?
+            .id()
+    };
+    let base = repo.merge_base(tip, parent_tip)?;
+
+    let meta = BranchMeta { parent, base };
+    meta::write(repo, branch, &meta)?;
+    Ok(meta)
+}
@@ -0,0 +1,16 @@
+use git2::{build::CheckoutBuilder, BranchType, Repository, StatusOptions};
+
+/// Checks out the local branch `name`, refusing to overwrite local changes.
+pub fn checkout_branch(repo: &Repository, name: &str) -> Result<(), git2::Error> {
+    let branch_ref = repo.find_branch(name, BranchType::Local)?.into_reference();
+    let tree = branch_ref.peel_to_tree()?;
+    repo.checkout_tree(tree.as_object(), Some(CheckoutBuilder::new().safe()))?;
+    repo.set_head(branch_ref.name().unwrap_or_default())
+}
+
+/// Whether tracked files have staged or unstaged modifications.
+pub fn has_uncommitted_changes(repo: &Repository) -> Result<bool, git2::Error> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+    Ok(!repo.statuses(Some(&mut options))?.is_empty())
+}
@@ -0,0 +1,131 @@
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use clap::ValueEnum;
+
+/// How commit timestamps are shown.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum DateFormat {
+    /// Relative to now, e.g. "3 hours ago"
+    #[default]
+    Relative,
+    /// ISO 8601-like, in the author's timezone, e.g. "2024-06-20 16:13:20 +0200"
+    Iso,
+    /// RFC 2822, in the author's timezone, e.g. "Thu, 20 Jun 2024 16:13:20 +0200"
+    Rfc2822,
+    /// In the local timezone, e.g. "Thu Jun 20 14:13:20 2024"
+    Local,
+}
+
+/// Formats a commit's author timestamp. `now` is the current Unix time, used
+/// by [`DateFormat::Relative`].
+pub fn format_time(time: git2::Time, format: DateFormat, now: i64) -> String {
+    let utc = match DateTime::<Utc>::from_timestamp(time.seconds(), 0) {
+        Some(t) => t,
+        None => return time.seconds().to_string(),
+    };
+    let offset = FixedOffset::east_opt(time.offset_minutes() * 60)
+        .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+
+    match format {
+        DateFormat::Relative => relative(now - time.seconds()),
+        DateFormat::Iso => utc
+            .with_timezone(&offset)
+            .format("%Y-%m-%d %H:%M:%S %z")
+            .to_string(),
+        DateFormat::Rfc2822 => utc.with_timezone(&offset).to_rfc2822(),
+        DateFormat::Local => utc
+            .with_timezone(&Local)
+            .format("%a %b %-d %H:%M:%S %Y")
+            .to_string(),
+    }
+}
+
+/// Describes an age in seconds the way `git log --date=relative` does.
+fn relative(age: i64) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+
+    if age < 0 {
+        return "in the future".to_string();
+    }
+    if age < 90 {
+        return plural(age, "second");
+    }
+    if age < 90 * MINUTE {
+        return plural((age + MINUTE / 2) / MINUTE, "minute");
+    }
+    if age < 36 * HOUR {
+        return plural((age + HOUR / 2) / HOUR, "hour");
+    }
+    let days = (age + DAY / 2) / DAY;
+    if days < 14 {
+        return plural(days, "day");
+    }
+    if days < 70 {
+        return plural((days + 3) / 7, "week");
+    }
+    if days < 365 {
+        return plural((days + 15) / 30, "month");
+    }
+    let mut years = days / 365;
+    let mut months = (days % 365 + 15) / 30;
+    if months >= 12 {
+        years += 1;
+        months = 0;
+    }
+    if years < 5 && months > 0 {
+        return format!(
+            "{}, {}",
+            plural_bare(years, "year"),
+            plural(months, "month")
+        );
+    }
+    plural(years, "year")
+}
+
+fn plural(n: i64, unit: &str) -> String {
+    format!("{} ago", plural_bare(n, unit))
+}
+
+fn plural_bare(n: i64, unit: &str) -> String {
+    if n == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{n} {unit}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::relative;
+
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+
+    #[test]
+    fn relative_rounds_at_the_unit_boundaries() {
+        let cases = [
+            (-5, "in the future"),
+            (1, "1 second ago"),
+            (89, "89 seconds ago"),
+            (90, "2 minutes ago"),
+            (89 * MINUTE, "89 minutes ago"),
+            (90 * MINUTE, "2 hours ago"),
+            (35 * HOUR, "35 hours ago"),
+            (36 * HOUR, "2 days ago"),
+            (13 * DAY, "13 days ago"),
+            (14 * DAY, "2 weeks ago"),
+            (69 * DAY, "10 weeks ago"),
+            (70 * DAY, "2 months ago"),
+            (364 * DAY, "12 months ago"),
+            (365 * DAY, "1 year ago"),
+            (400 * DAY, "1 year, 1 month ago"),
+            (725 * DAY, "2 years ago"),
+            (5 * 365 * DAY + 100 * DAY, "5 years ago"),
+        ];
+        for (age, expected) in cases {
+            assert_eq!(relative(age), expected, "age {age}s");
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::{fmt, io, process::ExitCode};
+
+/// Everything that can make a gx command fail.
+///
+/// Each kind of failure exits with its own code so scripts can tell them apart:
+///
+/// | Code | Failure                                                      |
+/// |------|--------------------------------------------------------------|
+/// | 1    | Anything else, e.g. uncommitted changes or a failed push     |
+/// | 2    | Invalid command line (reported by the argument parser)       |
+/// | 3    | Not inside a git repository                                  |
+/// | 4    | HEAD is not on a local branch                                |
+/// | 5    | History gx cannot handle, such as merges a rebase would drop |
+/// | 6    | A restack or sync stopped on conflicts                       |
+/// | 7    | A git operation failed                                       |
+/// | 8    | A GitHub API request failed                                  |
+///
+/// [`EXIT_CODES`] repeats the table for `gx --help`.
+pub const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  1  Any other failure, e.g. uncommitted changes or a rejected push
+  2  Invalid command line
+  3  Not inside a git repository
+  4  HEAD is not on a local branch
+  5  History gx cannot handle, such as merges a rebase would drop
+  6  A restack or sync stopped on conflicts
+  7  A git operation failed
+  8  A GitHub API request failed";
+
+#[derive(Debug)]
+pub enum GxError {
+    NotARepository,
+    /// HEAD is detached; the hint says which branch to switch to instead.
+    DetachedHead {
+        hint: String,
+    },
+    /// The repository is not in a state the command can run in.
+    InvalidState(String),
+    UnsupportedHistory(String),
+    /// An operation stopped on conflicts and was saved to be continued.
+    Conflict(String),
+    Git(git2::Error),
+    Forge(String),
+    Io(io::Error),
+    Other(String),
+}
+
+impl GxError {
+    /// A detached HEAD error that tells the user to switch to `what`.
+    pub fn detached(what: &str) -> GxError {
+        GxError::DetachedHead {
+            hint: format!("Switch to {what}."),
+        }
+    }
+
+    pub fn not_in_stack(branch: &str) -> GxError {
+        GxError::InvalidState(format!("Branch {branch} is not part of a stack."))
+    }
+
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            GxError::InvalidState(_) | GxError::Io(_) | GxError::Other(_) => 1,
+            GxError::NotARepository => 3,
+            GxError::DetachedHead { .. } => 4,
+            GxError::UnsupportedHistory(_) => 5,
+            GxError::Conflict(_) => 6,
+            GxError::Git(_) => 7,
+            GxError::Forge(_) => 8,
+        })
+    }
+}
+
+impl fmt::Display for GxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GxError::NotARepository => write!(f, "Not a git repository."),
+            GxError::DetachedHead { hint } => write!(
+                f,
+                "HEAD is not currently pointing to a local branch. {hint}"
+            ),
+            GxError::InvalidState(message)
+            | GxError::UnsupportedHistory(message)
+            | GxError::Conflict(message)
+            | GxError::Forge(message)
+            | GxError::Other(message) => write!(f, "{message}"),
+            GxError::Git(e) => write!(f, "git: {}", e.message()),
+            GxError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for GxError {}
+
+impl From<git2::Error> for GxError {
+    fn from(e: git2::Error) -> GxError {
+        GxError::Git(e)
+    }
+}
+
+impl From<io::Error> for GxError {
+    fn from(e: io::Error) -> GxError {
+        GxError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for GxError {
+    fn from(e: serde_json::Error) -> GxError {
+        GxError::Other(e.to_string())
+    }
+}
+
+impl From<String> for GxError {
+    fn from(message: String) -> GxError {
+        GxError::Other(message)
+    }
+}
+
+impl From<&str> for GxError {
+    fn from(message: &str) -> GxError {
+        GxError::Other(message.to_string())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::report;
+use colored::Colorize;
+use git2::{BranchType, Repository};
+use gx::checkout::has_uncommitted_changes;
+use gx::error::GxError;
+use gx::meta;
+use gx::restack;
+use gx::stack::Stack;
+use std::collections::HashSet;
+
+/// How `gx stack fold` adds the current branch's commits to its parent.
+pub struct FoldOptions<'a> {
+    /// Add them as a single commit instead of as they are.
+    pub squash: bool,
+    /// Message of the squashed commit. Defaults to the messages of the folded
+    /// commits, oldest first.
+    pub message: Option<&'a str>,
+    /// Rebase away merge commits in the branches that get restacked, see
+    /// [`restack::restack`].
+    pub linearize: bool,
+}
+
+/// Moves the current branch's commits onto its parent, stacks the branches
+/// above it on the parent instead, deletes it and restacks the branches that
+/// moved.
+pub fn fold(
+    repo: &Repository,
+    trunk_name: Option<&str>,
+    options: &FoldOptions,
+) -> Result<(), GxError> {
+    restack::ensure_no_operation(repo)?;
+    let (mut stack, head_branch) = Stack::current(repo, trunk_name, "to fold it")?;
+    let branch = stack.find(&head_branch).unwrap().clone();
+    let parent = match stack.find(&branch.parent) {
+        Some(p) => p.clone(),
+        None => {
+            return Err(GxError::InvalidState(format!(
+                "{head_branch} is stacked directly on the trunk, so there is no branch to fold it into."
+            )))
+        }
+    };
+    if branch.base != parent.tip {
+        return Err(GxError::InvalidState(format!(
+            "{head_branch} is not on top of the current tip of {}. Run `gx stack restack` first.",
+            parent.name
+        )));
+    }
+
+    // The parent's other children always move. The folded branch's children
+    // only do if its commits are squashed.
+    let mut moving = HashSet::new();
+    for b in &stack.branches {
+        let starts_move = if b.parent == parent.name {
+            b.name != head_branch
+        } else {
+            options.squash && b.parent == head_branch
+        };
+        if starts_move || moving.contains(&b.parent) {
+            moving.insert(b.name.clone());
+        }
+    }
+    let pending: Vec<String> = stack
+        .branches
+        .iter()
+        .filter(|b| moving.contains(&b.name))
+        .map(|b| b.name.clone())
+        .collect();
+    if !options.linearize {
+        let names: Vec<&str> = pending.iter().map(String::as_str).collect();
+        if let Some((name, oid)) = restack::find_merge(repo, &stack, &names)? {
+            return Err(restack::merge_refusal("fold", &name, oid));
+        }
+    }
+    if !pending.is_empty() && has_uncommitted_changes(repo)? {
+        return Err(GxError::InvalidState(
+            "You have uncommitted changes. Commit or stash them before folding, so the branches that move can be restacked.".to_string(),
+        ));
+    }
+
+    // Taken before the parent moves and the branch is deleted, so aborting
+    // the restack brings both back.
+    let original = restack::original_branches(repo, &stack)?;
+    let commits = stack.commits(repo, &branch)?;
+    let new_tip = if options.squash && !commits.is_empty() {
+        let tip = repo.find_commit(branch.tip)?;
+        let oldest = repo.find_commit(*commits.last().unwrap())?;
+        let message = match options.message {
+            Some(m) => m.to_string(),
+            None => {
+                let mut messages = Vec::new();
+                for oid in commits.iter().rev() {
+                    let commit = repo.find_commit(*oid)?;
+                    messages.push(commit.message().unwrap_or_default().trim_end().to_string());
+                }
+                messages.join("\n\n")
+            }
+        };
+        let author = oldest.author();
+        repo.commit(
+            None,
+            &author,
+            &repo.signature()?,
+            &message,
+            &tip.tree()?,
+            &[&repo.find_commit(parent.tip)?],
+        )?
+    } else {
+        branch.tip
+    };
+
+    // The parent ends up with the same files as the folded branch, so HEAD can
+    // move there without touching the working tree.
+    repo.reference(
+        &format!("refs/heads/{}", parent.name),
+        new_tip,
+        true,
+        "gx fold",
+    )?;
+    repo.set_head(&format!("refs/heads/{}", parent.name))?;
+    repo.find_branch(&head_branch, BranchType::Local)?
+        .delete()?;
+    meta::remove(repo, &head_branch)?;
+    println!(
+        "{} {} into {} ({} -> {})",
+        "Folded".green().bold(),
+        head_branch.yellow().bold(),
+        parent.name.yellow().bold(),
+        &parent.tip.to_string()[0..7],
+        &new_tip.to_string()[0..7],
+    );
+
+    stack.branches.retain(|b| b.name != head_branch);
+    for b in stack.branches.iter_mut() {
+        if b.name == parent.name {
+            b.tip = new_tip;
+        }
+        if b.parent == head_branch {
+            b.parent = parent.name.clone();
+        }
+    }
+    restack::record_stack(repo, &stack)?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let mut op = restack::start_operation(repo, "fold", &stack, &parent.name, pending)?;
+    op.original = original;
+    report::restack(restack::run_operation(repo, op)?)
+}
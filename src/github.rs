@@ -0,0 +1,234 @@
+use crate::error::GxError;
+use git2::Repository;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::env;
+
+/// API used unless `GX_GITHUB_API_URL` or `gx.githubApiUrl` says otherwise.
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Environment variables the API token is read from, in order.
+const TOKEN_VARS: [&str; 2] = ["GITHUB_TOKEN", "GH_TOKEN"];
+
+#[derive(Debug, Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub html_url: String,
+    /// `open` or `closed`, whether merged or not.
+    pub state: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub merged_at: Option<String>,
+    pub body: Option<String>,
+    pub base: PullRequestRef,
+}
+
+impl PullRequest {
+    /// `draft`, `open`, `merged` or `closed`, as shown in listings.
+    pub fn status(&self) -> &'static str {
+        match (self.state.as_str(), self.draft, &self.merged_at) {
+            ("open", true, _) => "draft",
+            ("open", false, _) => "open",
+            (_, _, Some(_)) => "merged",
+            _ => "closed",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PullRequestRef {
+    #[serde(rename = "ref")]
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+/// Client for the pull request endpoints of one GitHub repository.
+pub struct GitHub {
+    agent: ureq::Agent,
+    api_url: String,
+    token: String,
+    /// `owner/name` of the repository.
+    slug: String,
+}
+
+impl GitHub {
+    /// Sets up a client for the GitHub repository behind `remote_name`.
+    ///
+    /// The repository can be overridden with `gx.githubRepo` (as `owner/name`)
+    /// and the API endpoint with `GX_GITHUB_API_URL` or `gx.githubApiUrl`, which
+    /// is how GitHub Enterprise or a local mock server is used.
+    pub fn from_repo(repo: &Repository, remote_name: &str) -> Result<GitHub, GxError> {
+        let config = repo.config()?;
+
+        let token = TOKEN_VARS
+            .iter()
+            .find_map(|var| env::var(var).ok().filter(|t| !t.is_empty()))
+            .ok_or_else(|| {
+                GxError::Forge(
+                    "Set GITHUB_TOKEN or GH_TOKEN to a GitHub token to submit pull requests."
+                        .to_string(),
+                )
+            })?;
+
+        let api_url = match env::var("GX_GITHUB_API_URL") {
+            Ok(url) => url,
+            Err(_) => config
+                .get_string("gx.githubApiUrl")
+                .unwrap_or_else(|_| DEFAULT_API_URL.to_string()),
+        };
+
+        let slug = match config.get_string("gx.githubRepo") {
+            Ok(slug) => slug,
+            Err(_) => {
+                let remote = repo.find_remote(remote_name)?;
+                let url = remote.url().unwrap_or_default();
+                parse_slug(url).ok_or_else(|| {
+                    GxError::Forge(format!("Could not tell the GitHub repository from {remote_name}'s URL {url}. Set it with `git config gx.githubRepo owner/name`."))
+                })?
+            }
+        };
+
+        Ok(GitHub {
+            agent: ureq::AgentBuilder::new().build(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+            slug,
+        })
+    }
+
+    /// Owner of the repository, used to qualify head branch names.
+    pub fn owner(&self) -> &str {
+        self.slug.split('/').next().unwrap_or_default()
+    }
+
+    pub fn get_pr(&self, number: u64) -> Result<PullRequest, GxError> {
+        let url = format!("{}/repos/{}/pulls/{number}", self.api_url, self.slug);
+        let response = self.request("GET", &url).call();
+        handle(response)
+    }
+
+    /// The open pull request whose head is `branch`, if there is one.
+    pub fn find_open_pr(&self, branch: &str) -> Result<Option<PullRequest>, GxError> {
+        let url = format!("{}/repos/{}/pulls", self.api_url, self.slug);
+        let response = self
+            .request("GET", &url)
+            .query("head", &format!("{}:{branch}", self.owner()))
+            .query("state", "open")
+            .call();
+        let prs: Vec<PullRequest> = handle(response)?;
+        Ok(prs.into_iter().next())
+    }
+
+    pub fn create_pr(
+        &self,
+        head: &str,
+        base: &str,
+        title: &str,
+        body: &str,
+    ) -> Result<PullRequest, GxError> {
+        let url = format!("{}/repos/{}/pulls", self.api_url, self.slug);
+        let response = self.request("POST", &url).send_json(json!({
+            "head": head,
+            "base": base,
+            "title": title,
+            "body": body,
+        }));
+        handle(response)
+    }
+
+    /// Changes the base branch of pull request `number`.
+    pub fn update_base(&self, number: u64, base: &str) -> Result<PullRequest, GxError> {
+        let url = format!("{}/repos/{}/pulls/{number}", self.api_url, self.slug);
+        let response = self
+            .request("PATCH", &url)
+            .send_json(json!({ "base": base }));
+        handle(response)
+    }
+
+    /// Replaces the description of pull request `number`.
+    pub fn update_body(&self, number: u64, body: &str) -> Result<PullRequest, GxError> {
+        let url = format!("{}/repos/{}/pulls/{number}", self.api_url, self.slug);
+        let response = self
+            .request("PATCH", &url)
+            .send_json(json!({ "body": body }));
+        handle(response)
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        self.agent
+            .request(method, url)
+            .set("Accept", "application/vnd.github+json")
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .set("User-Agent", "gx")
+            .set("X-GitHub-Api-Version", "2022-11-28")
+    }
+}
+
+/// Turns error statuses into errors carrying GitHub's message, and decodes
+/// the body of successful responses.
+fn handle<T: DeserializeOwned>(
+    response: Result<ureq::Response, ureq::Error>,
+) -> Result<T, GxError> {
+    match response {
+        Ok(r) => r.into_json().map_err(|e| {
+            GxError::Forge(format!("GitHub API returned an unexpected response: {e}"))
+        }),
+        Err(ureq::Error::Status(code, r)) => {
+            let message = r
+                .into_json::<ApiError>()
+                .map(|e| e.message)
+                .unwrap_or_else(|_| "no details".to_string());
+            Err(GxError::Forge(format!(
+                "GitHub API request failed ({code}): {message}"
+            )))
+        }
+        Err(e) => Err(GxError::Forge(format!("GitHub API request failed: {e}"))),
+    }
+}
+
+/// Extracts `owner/name` from a GitHub remote URL such as
+/// `git@github.com:owner/name.git` or `https://github.com/owner/name`.
+fn parse_slug(url: &str) -> Option<String> {
+    let path = if let Some(rest) = url.strip_prefix("git@") {
+        rest.split_once(':')?.1
+    } else {
+        let without_scheme = url.split_once("://")?.1;
+        without_scheme.split_once('/')?.1
+    };
+    let path = path.trim_end_matches('/').trim_end_matches(".git");
+    let mut parts = path.split('/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(owner), Some(name), None) if !owner.is_empty() && !name.is_empty() => {
+            Some(format!("{owner}/{name}"))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_slug;
+
+    #[test]
+    fn parse_slug_reads_ssh_and_https_urls() {
+        let cases = [
+            ("git@github.com:owner/name.git", Some("owner/name")),
+            ("git@github.com:owner/name", Some("owner/name")),
+            ("https://github.com/owner/name", Some("owner/name")),
+            ("https://github.com/owner/name.git", Some("owner/name")),
+            ("https://github.com/owner/name/", Some("owner/name")),
+            ("ssh://git@github.com/owner/name.git", Some("owner/name")),
+            ("https://github.com/owner", None),
+            ("https://github.com/owner/name/extra", None),
+            ("/srv/git/name.git", None),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(parse_slug(url).as_deref(), expected, "{url}");
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Machine-readable output of `gx stack list --json`.
+//!
+//! The document is versioned through its `version` field. Adding fields keeps
+//! the version; removing, renaming or changing the meaning of a field bumps it.
+//!
+//! Version 1:
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "trunk": { "name": "main", "oid": "<40 hex chars>" },
+//!   "current": "feature-b",           // branch the stack was listed for
+//!   "detached_head": null | "<40 hex chars>", // commit of a detached HEAD in `current`
+//!   "operation": null | {             // restack stopped on conflicts
+//!     "kind": "restack",
+//!     "branch": "feature-b"           // branch being rebased
+//!   },
+//!   "branches": [                     // parents before children
+//!     {
+//!       "name": "feature-a",
+//!       "tip": "<40 hex chars>",
+//!       "parent": "main",             // the trunk's name for bottom branches
+//!       "base": "<40 hex chars>",     // commit the branch's own commits start after
+//!       "upstream": "origin/feature-a" | null,
+//!       "ahead": 2 | null,            // commits not on the upstream, null without one
+//!       "behind": 0 | null,           // upstream commits not on the branch
+//!       "upstream_gone": false,       // upstream configured but deleted on the remote
+//!       "needs_push": true,           // ahead of the upstream, or never pushed
+//!       "needs_restack": false,       // parent moved since the last restack
+//!       "commits": [                  // own first-parent commits, newest first
+//!         {
+//!           "oid": "<40 hex chars>",
+//!           "parents": ["<40 hex chars>"], // more than one for merge commits
+//!           "summary": "Add the thing",
+//!           "author": { "name": "A U Thor", "email": "author@example.com" },
+//!           "timestamp": 1718900000,  // author time, in seconds since the Unix epoch
+//!           "offset_minutes": 120     // author's timezone offset from UTC
+//!         }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! On the trunk, or with HEAD detached on the trunk's history, there is no
+//! stack to list: `current` is the trunk's name and `branches` is empty.
+
+use crate::error::GxError;
+use crate::stack::Stack;
+use crate::state::Operation;
+use crate::status;
+use crate::trunk::Trunk;
+use git2::{Oid, Repository};
+use serde::Serialize;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct StackDocument {
+    pub version: u32,
+    pub trunk: TrunkEntry,
+    pub current: String,
+    pub detached_head: Option<String>,
+    pub operation: Option<OperationEntry>,
+    pub branches: Vec<BranchEntry>,
+}
+
+#[derive(Serialize)]
+pub struct TrunkEntry {
+    pub name: String,
+    pub oid: String,
+}
+
+#[derive(Serialize)]
+pub struct OperationEntry {
+    pub kind: String,
+    pub branch: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BranchEntry {
+    pub name: String,
+    pub tip: String,
+    pub parent: String,
+    pub base: String,
+    pub upstream: Option<String>,
+    pub ahead: Option<usize>,
+    pub behind: Option<usize>,
+    pub upstream_gone: bool,
+    pub needs_push: bool,
+    pub needs_restack: bool,
+    pub commits: Vec<CommitEntry>,
+}
+
+#[derive(Serialize)]
+pub struct CommitEntry {
+    pub oid: String,
+    pub parents: Vec<String>,
+    pub summary: String,
+    pub author: AuthorEntry,
+    pub timestamp: i64,
+    pub offset_minutes: i32,
+}
+
+#[derive(Serialize)]
+pub struct AuthorEntry {
+    pub name: String,
+    pub email: String,
+}
+
+/// Describes `stack` as listed for branch `current`, or for the commit
+/// `detached_head` of that branch when HEAD is detached.
+pub fn stack_document(
+    repo: &Repository,
+    stack: &Stack,
+    current: &str,
+    detached_head: Option<Oid>,
+    operation: Option<&Operation>,
+) -> Result<StackDocument, GxError> {
+    let mut branches = Vec::new();
+    for b in &stack.branches {
+        let status = status::branch_status(repo, stack, b)?;
+
+        let mut commits = Vec::new();
+        for oid in stack.commits(repo, b)? {
+            let commit = repo.find_commit(oid)?;
+            let author = commit.author();
+            commits.push(CommitEntry {
+                oid: oid.to_string(),
+                parents: commit.parent_ids().map(|p| p.to_string()).collect(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+                author: AuthorEntry {
+                    name: author.name().unwrap_or_default().to_string(),
+                    email: author.email().unwrap_or_default().to_string(),
+                },
+                timestamp: author.when().seconds(),
+                offset_minutes: author.when().offset_minutes(),
+            });
+        }
+
+        branches.push(BranchEntry {
+            name: b.name.clone(),
+            tip: b.tip.to_string(),
+            parent: b.parent.clone(),
+            base: b.base.to_string(),
+            upstream: status.upstream,
+            ahead: status.ahead,
+            behind: status.behind,
+            upstream_gone: status.upstream_gone,
+            needs_push: status.needs_push,
+            needs_restack: status.needs_restack,
+            commits,
+        });
+    }
+
+    Ok(StackDocument {
+        version: SCHEMA_VERSION,
+        trunk: TrunkEntry {
+            name: stack.trunk.name.clone(),
+            oid: stack.trunk.oid.to_string(),
+        },
+        current: current.to_string(),
+        detached_head: detached_head.map(|oid| oid.to_string()),
+        operation: operation.map(|op| OperationEntry {
+            kind: op.kind.clone(),
+            branch: op.pending.first().cloned(),
+        }),
+        branches,
+    })
+}
+
+/// Describes the empty listing for HEAD on `trunk`, or detached at
+/// `detached_head` on its history.
+pub fn trunk_document(trunk: &Trunk, detached_head: Option<Oid>) -> StackDocument {
+    StackDocument {
+        version: SCHEMA_VERSION,
+        trunk: TrunkEntry {
+            name: trunk.name.clone(),
+            oid: trunk.oid.to_string(),
+        },
+        current: trunk.name.clone(),
+        detached_head: detached_head.map(|oid| oid.to_string()),
+        operation: None,
+        branches: Vec::new(),
+    }
+}
@@ -0,0 +1,38 @@
+//! Stacked branches on top of git.
+//!
+//! The library builds the stack model gx works with from a
+//! [`git2::Repository`] and carries out the operations that rewrite and
+//! publish stacks, such as [`restack`], [`sync`] and [`push`]. It returns
+//! plain data describing what happened, leaving all output to the caller.
+//! The `gx` binary is a presentation layer on top of it.
+//!
+//! ```no_run
+//! use git2::Repository;
+//! use gx::stack::Stack;
+//! use gx::trunk;
+//!
+//! let repo = Repository::open(".")?;
+//! let trunk = trunk::find_trunk(&repo, None)?;
+//! for stack in Stack::all(&repo, trunk)? {
+//!     for branch in &stack.branches {
+//!         let commits = stack.commits(&repo, branch)?;
+//!         println!("{} on {}: {} commits", branch.name, branch.parent, commits.len());
+//!     }
+//! }
+//! # Ok::<(), gx::error::GxError>(())
+//! ```
+
+pub mod branch;
+pub mod checkout;
+pub mod error;
+pub mod github;
+pub mod json;
+pub mod meta;
+pub mod push;
+pub mod remote;
+pub mod restack;
+pub mod stack;
+pub mod state;
+pub mod status;
+pub mod sync;
+pub mod trunk;
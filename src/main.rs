@@ -1,11 +1,36 @@
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand};
 use colored::Colorize;
-use git2::{Branch, BranchType, Oid, Repository};
-use std::{collections::HashMap, error::Error};
+use date::DateFormat;
+use fold::FoldOptions;
+use git2::{Oid, Repository};
+use gx::error::{self, GxError};
+use gx::github::GitHub;
+use gx::stack::{self, Stack};
+use gx::{branch, json, meta, push, remote, restack, state, sync, trunk};
+use modify::ModifyOptions;
+use split::SplitBy;
+use std::io::{self, ErrorKind, Write};
+use std::process::ExitCode;
+
+mod absorb;
+mod date;
+mod fold;
+mod modify;
+mod nav;
+mod prompt;
+mod render;
+mod report;
+mod split;
+mod submit;
 
 /// gx - git xtended
 #[derive(Parser, Debug)]
+#[command(after_long_help = error::EXIT_CODES)]
 struct Cli {
+    /// Trunk branch stacks are based on (defaults to `gx.trunk`, `origin/HEAD`, `main` or `master`)
+    #[arg(long, global = true)]
+    trunk: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -17,192 +42,582 @@ enum Commands {
         #[command(subcommand)]
         command: StackCommands,
     },
+    /// Check out the branch stacked on top of the current one
+    Up {
+        /// Number of branches to move up
+        #[arg(default_value_t = 1)]
+        steps: usize,
+    },
+    /// Check out the parent of the current branch
+    Down {
+        /// Number of branches to move down
+        #[arg(default_value_t = 1)]
+        steps: usize,
+    },
+    /// Check out the topmost branch of the current stack
+    Top,
+    /// Check out the bottommost branch of the current stack
+    Bottom,
+    /// Fold the staged hunks into the stack commits that last touched those lines, and restack
+    Absorb,
+    /// List every stack in the repository (same as `gx stack list --all`)
+    Stacks {
+        /// How to show commit dates
+        #[arg(long, value_enum, default_value_t)]
+        date: DateFormat,
+    },
 }
 
 #[derive(Subcommand, Debug)]
 enum StackCommands {
     /// List all commits in the current stack
-    List,
+    List {
+        /// Print the stack as a JSON document (schema version 1) instead of a tree
+        #[arg(long)]
+        json: bool,
+        /// List every stack in the repository, not only the current one
+        #[arg(long, conflicts_with = "json")]
+        all: bool,
+        /// How to show commit dates
+        #[arg(long, value_enum, default_value_t)]
+        date: DateFormat,
+    },
+    /// Create a new branch on top of the current one and check it out
+    Create {
+        /// Name of the new branch
+        name: String,
+        /// Commit the staged changes to the new branch with this message
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+    /// Amend the current branch with the staged changes and restack the branches above it
+    Modify {
+        /// Add a new commit instead of amending the last one
+        #[arg(long)]
+        commit: bool,
+        /// Stage all changes to tracked files first
+        #[arg(short, long)]
+        all: bool,
+        /// Message of the new commit, or the new message of the amended one
+        #[arg(short, long)]
+        message: Option<String>,
+        /// Rebase away merge commits in the branches above, replaying the commits they brought in
+        #[arg(long)]
+        linearize: bool,
+    },
+    /// Split the current branch into a chain of branches and restack the branches above it
+    #[command(group(ArgGroup::new("by").required(true).args(["commit", "file"])))]
+    Split {
+        /// End a new branch at a commit; it takes the commits after the previous one
+        #[arg(long = "commit", value_name = "COMMIT=BRANCH")]
+        commit: Vec<String>,
+        /// Move the changes to files matching a glob to a new branch
+        #[arg(long, value_name = "GLOB=BRANCH")]
+        file: Vec<String>,
+    },
+    /// Fold the current branch into its parent, delete it and restack the branches that move
+    Fold {
+        /// Add the branch's commits to the parent as a single commit
+        #[arg(long)]
+        squash: bool,
+        /// Message of the squashed commit (defaults to the messages of the folded commits)
+        #[arg(short, long, requires = "squash")]
+        message: Option<String>,
+        /// Rebase away merge commits in the branches that move, replaying the commits they brought in
+        #[arg(long)]
+        linearize: bool,
+    },
+    /// Rebase every branch in the stack onto its parent's current tip
+    Restack {
+        /// Continue after resolving conflicts (same as `gx stack continue`)
+        #[arg(long = "continue", conflicts_with_all = ["abort", "skip"])]
+        continue_: bool,
+        /// Restore every branch to where it was before the restack (same as `gx stack abort`)
+        #[arg(long, conflicts_with = "skip")]
+        abort: bool,
+        /// Drop the conflicting commit and continue (same as `gx stack skip`)
+        #[arg(long)]
+        skip: bool,
+        /// Rebase away merge commits, replaying the commits they brought in
+        #[arg(long, conflicts_with_all = ["continue_", "abort", "skip"])]
+        linearize: bool,
+    },
+    /// Fetch the trunk, delete merged branches and rebase the stack onto the trunk
+    Sync {
+        /// Delete merged branches without asking
+        #[arg(short, long)]
+        force: bool,
+        /// Rebase away merge commits, replaying the commits they brought in
+        #[arg(long)]
+        linearize: bool,
+    },
+    /// Force-push every branch in the stack to its upstream, with a lease
+    Push,
+    /// Push the stack and create or update a GitHub pull request for each branch
+    Submit,
+    /// Continue a restack that stopped on conflicts
+    Continue,
+    /// Abort a restack that stopped on conflicts and restore every branch
+    Abort,
+    /// Drop the commit that caused the conflict and continue the restack
+    Skip,
+    /// Record the current branch's parent so the stack survives rewrites
+    Track {
+        /// Parent branch (defaults to the parent inferred from history)
+        #[arg(long)]
+        parent: Option<String>,
+    },
+    /// Forget the recorded parent of the current branch
+    Untrack,
 }
 
-fn get_local_branches(repo: &Repository) -> Result<HashMap<Oid, Branch>, Box<dyn Error>> {
-    let mut branches = HashMap::new();
-    for branch in repo.branches(Some(BranchType::Local))? {
-        let (branch, _) = branch?;
-        let maybe_oid = branch.get().target();
-        match maybe_oid {
-            Some(oid) => {
-                branches.insert(oid, branch);
+fn list_stack(
+    repo: &Repository,
+    trunk_name: Option<&str>,
+    json: bool,
+    date: DateFormat,
+) -> Result<(), GxError> {
+    let mut out = io::stdout().lock();
+    // While an operation is stopped HEAD is detached mid-rebase, so list the
+    // stack of the branch the operation started from instead.
+    let operation = state::read(repo)?;
+    if let (Some(op), false) = (&operation, json) {
+        report::write_in_progress(&mut out, op)?;
+    }
+
+    let head = repo.head()?;
+    let head_branch = match &operation {
+        Some(op) => op.head.clone(),
+        None if head.is_branch() => head.shorthand().unwrap_or_default().to_string(),
+        None => match rebasing_branch(repo)? {
+            Some(name) => {
+                if !json {
+                    writeln!(
+                        out,
+                        "{} A rebase of {} is in progress. Finish it with `git rebase --continue` or undo it with `git rebase --abort`.",
+                        "Note:".yellow().bold(),
+                        name.yellow().bold(),
+                    )?;
+                }
+                name
             }
             None => {
-                let branch_name = branch.name()?.unwrap_or("<unknown branch>");
-                println!("Error: Branch {branch_name} has no target.");
+                let oid = head.peel_to_commit()?.id();
+                return list_detached(repo, trunk_name, oid, json, date);
             }
+        },
+    };
+    let head_branch = head_branch.as_str();
+
+    let trunk = trunk::find_trunk(repo, trunk_name)?;
+    let trunk_display = trunk.name.clone();
+    if head_branch == trunk.name {
+        if json {
+            let document = json::trunk_document(&trunk, None);
+            writeln!(out, "{}", serde_json::to_string_pretty(&document)?)?;
+        } else {
+            writeln!(
+                out,
+                "HEAD is on trunk branch {}; there is no stack to list.",
+                trunk.name
+            )?;
+        }
+        return Ok(());
+    }
+    let stack = match Stack::for_branch(repo, trunk, head_branch)? {
+        Some(s) => s,
+        None => {
+            return Err(GxError::InvalidState(format!(
+                "Branch {head_branch} is not part of a stack on {trunk_display}."
+            )))
         }
+    };
+
+    if json {
+        let document = json::stack_document(repo, &stack, head_branch, None, operation.as_ref())?;
+        writeln!(out, "{}", serde_json::to_string_pretty(&document)?)?;
+        return Ok(());
+    }
+
+    for line in render::render_stack(repo, &stack, head_branch, None, date)? {
+        writeln!(out, "{line}")?;
     }
-    Ok(branches)
+
+    Ok(())
 }
 
-fn list_stack(repo: &Repository) -> Result<(), Box<dyn Error>> {
-    let head = repo.head()?;
-    if !head.is_branch() {
-        println!("Error: HEAD is not currently pointing to a local branch. Switch to a local branch to list the stack.");
+/// Lists the stacks containing commit `oid`, which a detached HEAD points at,
+/// with the HEAD position marked.
+fn list_detached(
+    repo: &Repository,
+    trunk_name: Option<&str>,
+    oid: Oid,
+    json: bool,
+    date: DateFormat,
+) -> Result<(), GxError> {
+    let mut out = io::stdout().lock();
+    let trunk = trunk::find_trunk(repo, trunk_name)?;
+    let commit_hash = &oid.to_string()[0..7];
+    if oid == trunk.oid || repo.graph_descendant_of(trunk.oid, oid)? {
+        if json {
+            let document = json::trunk_document(&trunk, Some(oid));
+            writeln!(out, "{}", serde_json::to_string_pretty(&document)?)?;
+            return Ok(());
+        }
+        writeln!(out, "HEAD is detached at {commit_hash} on trunk branch {}; there is no stack to list. Run `git switch {}` to get back onto it.", trunk.name, trunk.name)?;
         return Ok(());
     }
 
-    let local_branches = get_local_branches(repo)?;
-
-    let mut curr = head.peel_to_commit();
-    let mut num_commits = 0;
-    while let Ok(commit) = curr {
-        let commit_id = commit.id();
-        let commit_hash = &commit_id.to_string()[0..7];
-
-        let commit_desc = commit.summary().unwrap_or("<no summary>");
-        let commit_time = commit.time().seconds().to_string();
-        let commit_author = commit.author().name().unwrap_or("Unknown").bold();
-
-        let commit_branch =  local_branches
-            .get(&commit_id)
-            .and_then(|branch| branch.name().ok().flatten());
-        
-        let fmt_commit_hash = commit_hash.red().bold();
-        let fmt_commit_desc = commit_desc.bold();
-        let fmt_commit_time = format!("({})", commit_time).green().bold();
-        let fmt_commit_author = format!("<{}>", commit_author).blue().bold();
-        
-        match commit_branch {
-            Some(branch) => {
-                println!(
-                    "* {} - {} {} {} {}",
-                    fmt_commit_hash,
-                    format!("({})", branch).yellow().bold(),
-                    fmt_commit_desc,
-                    fmt_commit_time,
-                    fmt_commit_author,
-                );
-            }
-            None => {
-                println!(
-                    "* {} - {} {} {}",
-                    fmt_commit_hash,
-                    fmt_commit_desc,
-                    fmt_commit_time,
-                    fmt_commit_author,
-                );
-            }
+    let found = stack::stacks_containing(repo, &trunk, oid)?;
+    if found.is_empty() {
+        return Err(GxError::InvalidState(format!(
+            "HEAD is detached at {commit_hash}, which is not part of a stack on {}. Run `git switch <branch>` to get back onto a branch, or `git switch -c <name>` to start one here.",
+            trunk.name
+        )));
+    }
+
+    if json {
+        let (stack, branch) = &found[0];
+        let document = json::stack_document(repo, stack, branch, Some(oid), None)?;
+        writeln!(out, "{}", serde_json::to_string_pretty(&document)?)?;
+        return Ok(());
+    }
+
+    for (i, (stack, branch)) in found.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
         }
-        
-        num_commits += 1;
-        if num_commits == 10 {
-            break;
+        let at = if stack.find(branch).is_some_and(|b| b.tip == oid) {
+            "the tip of"
+        } else {
+            "a commit on"
+        };
+        writeln!(
+            out,
+            "{} HEAD is detached at {commit_hash}, {at} {}. Run `git switch {branch}` to get back onto the branch, or `git switch -c <name>` to start a new one here.",
+            "Note:".yellow().bold(),
+            branch.yellow().bold(),
+        )?;
+        for line in render::render_stack(repo, stack, "", Some(oid), date)? {
+            writeln!(out, "{line}")?;
         }
+    }
+    Ok(())
+}
 
-        if commit.parent_count() > 1 {
-            println!("Error: Commit {commit_hash} has more than one parent. Stacked PRs are not supported.");
-            return Ok(());
-        }
+/// Lists every stack on the trunk, each headed by its tip branches, the age of
+/// its newest commit and its pull requests. The stack HEAD is in is marked.
+fn list_all_stacks(
+    repo: &Repository,
+    trunk_name: Option<&str>,
+    date: DateFormat,
+) -> Result<(), GxError> {
+    let mut out = io::stdout().lock();
+    let head = repo.head()?;
+    let current = match state::read(repo)? {
+        Some(op) => op.head,
+        None if head.is_branch() => head.shorthand().unwrap_or_default().to_string(),
+        None => String::new(),
+    };
 
-        curr = commit.parent(0);
+    let trunk = trunk::find_trunk(repo, trunk_name)?;
+    let trunk_display = trunk.name.clone();
+    let stacks = Stack::all(repo, trunk)?;
+    if stacks.is_empty() {
+        writeln!(out, "There are no stacks on {trunk_display}.")?;
+        return Ok(());
     }
 
-    for branch in repo.branches(Some(BranchType::Local))? {
-        let (branch, _) = branch?;
+    refresh_pr_states(repo, &stacks)?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    for (i, stack) in stacks.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        let is_current = stack.find(&current).is_some();
 
-        let branch_name = match branch.name() {
-            Ok(Some(name)) => Some(name),
-            Ok(None) => {
-                println!("Found a branch with no name.");
-                None
+        let tips: Vec<String> = stack.tips().iter().map(|b| b.name.clone()).collect();
+        let mut newest = None;
+        for b in &stack.branches {
+            let time = repo.find_commit(b.tip)?.author().when();
+            if newest.is_none_or(|t: git2::Time| time.seconds() > t.seconds()) {
+                newest = Some(time);
             }
-            Err(e) => {
-                println!("Error: {:?}", e);
-                None
+        }
+        let age = newest
+            .map(|t| date::format_time(t, date, now))
+            .unwrap_or_default();
+        let mut prs = Vec::new();
+        for b in &stack.branches {
+            if let Some(n) = meta::read_pr(repo, &b.name)? {
+                match meta::read_pr_state(repo, &b.name)? {
+                    Some(state) => prs.push(format!("#{n} {state}")),
+                    None => prs.push(format!("#{n}")),
+                }
             }
+        }
+        let pr_status = if prs.is_empty() {
+            "no PRs".to_string()
+        } else {
+            format!("PRs {}", prs.join(", "))
         };
 
-        let upstream = match branch.upstream() {
-            Ok(u) => Some(u),
-            Err(e) => {
-                println!("Error: {:?}", e);
-                None
-            }
+        let (marker, name) = if is_current {
+            ("*".cyan().bold(), tips.join(", ").cyan().bold())
+        } else {
+            (" ".normal(), tips.join(", ").yellow().bold())
+        };
+        let count = match stack.branches.len() {
+            1 => "1 branch".to_string(),
+            n => format!("{n} branches"),
         };
+        writeln!(
+            out,
+            "{marker} {name} {}",
+            format!("({count}, last commit {age}, {pr_status})").dimmed()
+        )?;
+        for line in render::render_stack(repo, stack, &current, None, date)? {
+            writeln!(out, "{line}")?;
+        }
+    }
+    Ok(())
+}
 
-        let upstream_name = upstream.and_then(|u| match u.name() {
-            Ok(Some(name)) => Some(name.to_string()),
-            Ok(None) => {
-                println!("Found an upstream branch with no name.");
-                None
-            }
-            Err(e) => {
-                println!("Error: {:?}", e);
-                None
-            }
-        });
-
-        match (branch_name, upstream_name) {
-            (Some(b), Some(u)) => {
-                println!(
-                    "\u{25c9}  branch: {}, upstream: {}",
-                    b.blue().bold(),
-                    u.green().bold()
-                );
-                println!("\u{ff5c}");
-                println!("\u{ff5c}");
-                println!("\u{ff5c}");
-                println!(
-                    "\u{25cb}  branch: {}, upstream: {}",
-                    b.blue().bold(),
-                    u.green().bold()
-                );
-                println!("\u{ff5c}");
-                println!("\u{ff5c}");
-                println!("\u{ff5c}");
-                println!(
-                    "\u{29bf}  branch: {}, upstream: {}",
-                    b.blue().bold(),
-                    u.green().bold()
-                );
-            }
-            _ => {
-                println!("Skipping branch with no name.");
-                continue;
-            }
+/// Fetches the state of the pull requests recorded for the branches of
+/// `stacks` and caches it in their metadata. Without a GitHub token, or when
+/// GitHub cannot be reached, the states cached by the last submit or listing
+/// are shown instead.
+fn refresh_pr_states(repo: &Repository, stacks: &[Stack]) -> Result<(), GxError> {
+    let mut recorded = Vec::new();
+    for b in stacks.iter().flat_map(|s| &s.branches) {
+        if let Some(n) = meta::read_pr(repo, &b.name)? {
+            recorded.push((b.name.as_str(), n));
         }
     }
+    let Some(stack) = stacks.first().filter(|_| !recorded.is_empty()) else {
+        return Ok(());
+    };
+    let remote_name = remote::trunk_remote(repo, &stack.trunk);
+    let Ok(github) = GitHub::from_repo(repo, &remote_name) else {
+        return Ok(());
+    };
+    for (name, number) in recorded {
+        match github.get_pr(number) {
+            Ok(pr) => meta::write_pr_state(repo, name, pr.status())?,
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+/// Branch being rebased by a `git rebase` that stopped part way, if any.
+///
+/// git keeps it in `head-name` of its rebase state directory, which libgit2
+/// refuses to open for the interactive backend git uses by default.
+fn rebasing_branch(repo: &Repository) -> Result<Option<String>, GxError> {
+    for dir in ["rebase-merge", "rebase-apply"] {
+        let path = repo.path().join(dir).join("head-name");
+        if !path.exists() {
+            continue;
+        }
+        let name = std::fs::read_to_string(path)?;
+        let name = name.trim();
+        return Ok(name.strip_prefix("refs/heads/").map(str::to_string));
+    }
+    Ok(None)
+}
+
+fn create_branch(
+    repo: &Repository,
+    trunk_name: Option<&str>,
+    name: &str,
+    message: Option<&str>,
+) -> Result<(), GxError> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err(GxError::detached("the branch you want to stack on"));
+    }
+    let parent = head.shorthand().unwrap_or_default().to_string();
+
+    let trunk = trunk::find_trunk(repo, trunk_name)?;
+    branch::create(repo, &trunk, &parent, name, message)?;
+    println!(
+        "Created {} on top of {}.",
+        name.yellow().bold(),
+        parent.yellow().bold()
+    );
+    Ok(())
+}
+
+fn track_branch(
+    repo: &Repository,
+    trunk_name: Option<&str>,
+    parent: Option<&str>,
+) -> Result<(), GxError> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err(GxError::detached("the branch you want to track"));
+    }
+    let branch = head.shorthand().unwrap_or_default();
+
+    let trunk = trunk::find_trunk(repo, trunk_name)?;
+    let meta = branch::track(repo, &trunk, branch, parent)?;
+    println!(
+        "Tracking {} on top of {}.",
+        branch.yellow().bold(),
+        meta.parent.yellow().bold()
+    );
+    Ok(())
+}
+
+fn untrack_branch(repo: &Repository) -> Result<(), GxError> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Err(GxError::detached("the branch you want to untrack"));
+    }
+    let branch = head.shorthand().unwrap_or_default();
+    meta::remove(repo, branch)?;
+    println!("Stopped tracking {}.", branch.yellow().bold());
+    Ok(())
+}
 
+fn abort_operation(repo: &Repository) -> Result<(), GxError> {
+    let op = restack::abort_operation(repo)?;
+    report::aborted(&op);
     Ok(())
 }
 
-fn main() -> Result<(), git2::Error> {
+/// Fetches the trunk, asks before deleting the branches that landed in it and
+/// restacks the rest of the current stack.
+fn sync_stack(
+    repo: &Repository,
+    trunk_name: Option<&str>,
+    force: bool,
+    linearize: bool,
+) -> Result<(), GxError> {
+    let plan = sync::plan(repo, trunk_name)?;
+    report::sync_plan(&plan);
+    let delete = !plan.merged.is_empty() && (force || prompt::confirm("Delete them?")?);
+    let synced = sync::apply(repo, plan, delete, linearize)?;
+    for name in &synced.deleted {
+        println!("Deleted merged branch {}.", name.yellow().bold());
+    }
+    report::restack(synced.restack)
+}
+
+fn push_stack(repo: &Repository, trunk_name: Option<&str>) -> Result<(), GxError> {
+    if !report::push(&push::push(repo, trunk_name)?) {
+        return Err(GxError::Other(
+            "Some branches could not be pushed. Fetch and restack them, then push again."
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        // The reader went away, as with `gx stack list | head`.
+        Err(GxError::Io(e)) if e.kind() == ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            e.exit_code()
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), GxError> {
+    let repo = match Repository::open(".") {
+        Ok(r) => r,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Err(GxError::NotARepository),
+        Err(e) => return Err(e.into()),
+    };
+    let trunk = cli.trunk.as_deref();
 
     match cli.command {
-        Commands::Stack { command } => {
-            let repo = match Repository::open(".") {
-                Ok(r) => r,
-                Err(e) => {
-                    if e.code() == git2::ErrorCode::NotFound {
-                        println!("Error: Not a git repository.");
-                        return Ok(());
-                    } else {
-                        println!("Error: {:?}", e);
-                        return Ok(());
-                    }
+        Commands::Stack { command } => match command {
+            StackCommands::List { json, all, date } => {
+                if all {
+                    list_all_stacks(&repo, trunk, date)
+                } else {
+                    list_stack(&repo, trunk, json, date)
                 }
-            };
-            match command {
-                StackCommands::List => {
-                    let res = list_stack(&repo);
-                    match res {
-                        Ok(_) => {}
-                        Err(e) => println!("Error: {:?}", e),
-                    }
+            }
+            StackCommands::Create { name, message } => {
+                create_branch(&repo, trunk, &name, message.as_deref())
+            }
+            StackCommands::Modify {
+                commit,
+                all,
+                message,
+                linearize,
+            } => modify::modify(
+                &repo,
+                trunk,
+                &ModifyOptions {
+                    new_commit: commit,
+                    all,
+                    message: message.as_deref(),
+                    linearize,
+                },
+            ),
+            StackCommands::Split { commit, file } => {
+                let by = if commit.is_empty() {
+                    SplitBy::Files(&file)
+                } else {
+                    SplitBy::Commits(&commit)
+                };
+                split::split(&repo, trunk, by)
+            }
+            StackCommands::Fold {
+                squash,
+                message,
+                linearize,
+            } => fold::fold(
+                &repo,
+                trunk,
+                &FoldOptions {
+                    squash,
+                    message: message.as_deref(),
+                    linearize,
+                },
+            ),
+            StackCommands::Restack {
+                continue_,
+                abort,
+                skip,
+                linearize,
+            } => {
+                if continue_ {
+                    report::restack(restack::continue_operation(&repo)?)
+                } else if abort {
+                    abort_operation(&repo)
+                } else if skip {
+                    report::restack(restack::skip_commit(&repo)?)
+                } else {
+                    report::restack(restack::restack(&repo, trunk, linearize)?)
                 }
             }
-        }
+            StackCommands::Sync { force, linearize } => sync_stack(&repo, trunk, force, linearize),
+            StackCommands::Push => push_stack(&repo, trunk),
+            StackCommands::Submit => submit::submit(&repo, trunk),
+            StackCommands::Continue => report::restack(restack::continue_operation(&repo)?),
+            StackCommands::Abort => abort_operation(&repo),
+            StackCommands::Skip => report::restack(restack::skip_commit(&repo)?),
+            StackCommands::Track { parent } => track_branch(&repo, trunk, parent.as_deref()),
+            StackCommands::Untrack => untrack_branch(&repo),
+        },
+        Commands::Absorb => absorb::absorb(&repo, trunk),
+        Commands::Stacks { date } => list_all_stacks(&repo, trunk, date),
+        Commands::Up { steps } => nav::up(&repo, trunk, steps),
+        Commands::Down { steps } => nav::down(&repo, trunk, steps),
+        Commands::Top => nav::top(&repo, trunk),
+        Commands::Bottom => nav::bottom(&repo, trunk),
     }
-
-    Ok(())
 }
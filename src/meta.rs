@@ -0,0 +1,110 @@
+use git2::{Config, ConfigLevel, ErrorCode, Oid, Repository};
+
+/// Stack metadata gx records for a branch.
+///
+/// It lives in the branch's section of the repository's `.git/config`, next to
+/// git's own `remote` and `merge` keys:
+///
+/// ```text
+/// [branch "feature-b"]
+///     gx-parent = feature-a
+///     gx-base = 3f2c9a1...
+///     gx-pr = 42
+///     gx-pr-state = open
+/// ```
+///
+/// `gx-pr` is managed separately through [`read_pr`] and [`write_pr`], and
+/// the last known state of that pull request through [`read_pr_state`] and
+/// [`write_pr_state`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchMeta {
+    /// Branch this branch is stacked on top of.
+    pub parent: String,
+    /// Commit of `parent` this branch was created on or last restacked onto.
+    pub base: Oid,
+}
+
+fn parent_key(branch: &str) -> String {
+    format!("branch.{branch}.gx-parent")
+}
+
+fn base_key(branch: &str) -> String {
+    format!("branch.{branch}.gx-base")
+}
+
+fn pr_key(branch: &str) -> String {
+    format!("branch.{branch}.gx-pr")
+}
+
+fn pr_state_key(branch: &str) -> String {
+    format!("branch.{branch}.gx-pr-state")
+}
+
+fn local_config(repo: &Repository) -> Result<Config, git2::Error> {
+    repo.config()?.open_level(ConfigLevel::Local)
+}
+
+/// Reads the metadata recorded for `branch`, if any.
+pub fn read(repo: &Repository, branch: &str) -> Result<Option<BranchMeta>, git2::Error> {
+    let config = repo.config()?;
+    let parent = match config.get_string(&parent_key(branch)) {
+        Ok(p) => p,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let base = match config.get_string(&base_key(branch)) {
+        Ok(b) => Oid::from_str(&b)?,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(BranchMeta { parent, base }))
+}
+
+/// Records `meta` for `branch`, replacing any previous metadata.
+pub fn write(repo: &Repository, branch: &str, meta: &BranchMeta) -> Result<(), git2::Error> {
+    let mut config = local_config(repo)?;
+    config.set_str(&parent_key(branch), &meta.parent)?;
+    config.set_str(&base_key(branch), &meta.base.to_string())
+}
+
+/// Forgets the metadata recorded for `branch`. Missing keys are not an error.
+pub fn remove(repo: &Repository, branch: &str) -> Result<(), git2::Error> {
+    let mut config = local_config(repo)?;
+    for key in [parent_key(branch), base_key(branch)] {
+        match config.remove(&key) {
+            Ok(()) => {}
+            Err(e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Number of the pull request submitted for `branch`, if any.
+pub fn read_pr(repo: &Repository, branch: &str) -> Result<Option<u64>, git2::Error> {
+    match repo.config()?.get_i64(&pr_key(branch)) {
+        Ok(n) => Ok(u64::try_from(n).ok()),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Records the number of the pull request submitted for `branch`.
+pub fn write_pr(repo: &Repository, branch: &str, number: u64) -> Result<(), git2::Error> {
+    local_config(repo)?.set_i64(&pr_key(branch), number as i64)
+}
+
+/// State of the pull request of `branch` when gx last saw it, such as `open`
+/// or `merged`.
+pub fn read_pr_state(repo: &Repository, branch: &str) -> Result<Option<String>, git2::Error> {
+    match repo.config()?.get_string(&pr_state_key(branch)) {
+        Ok(state) => Ok(Some(state)),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Caches the state of the pull request of `branch`.
+pub fn write_pr_state(repo: &Repository, branch: &str, state: &str) -> Result<(), git2::Error> {
+    local_config(repo)?.set_str(&pr_state_key(branch), state)
+}
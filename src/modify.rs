@@ -0,0 +1,132 @@
+use crate::report;
+use colored::Colorize;
+use git2::Repository;
+use gx::error::GxError;
+use gx::restack;
+use gx::stack::Stack;
+
+/// How `gx stack modify` records the staged changes.
+pub struct ModifyOptions<'a> {
+    /// Add a new commit instead of amending the branch's last one.
+    pub new_commit: bool,
+    /// Stage every change to tracked files first, like `git commit -a`.
+    pub all: bool,
+    /// Message of the new commit, or the new message of the amended one.
+    pub message: Option<&'a str>,
+    /// Rebase away merge commits in the branches above, see
+    /// [`restack::restack`].
+    pub linearize: bool,
+}
+
+/// Commits the staged changes into the current branch, amending its last
+/// commit unless `new_commit` is set, and restacks every branch above it.
+pub fn modify(
+    repo: &Repository,
+    trunk_name: Option<&str>,
+    options: &ModifyOptions,
+) -> Result<(), GxError> {
+    restack::ensure_no_operation(repo)?;
+    let (mut stack, head_branch) = Stack::current(repo, trunk_name, "to modify it")?;
+    let head_commit = repo.head()?.peel_to_commit()?;
+    let branch = stack.find(&head_branch).unwrap().clone();
+
+    let descendants = stack.descendants(&head_branch);
+    if !options.linearize {
+        let names: Vec<&str> = descendants.iter().map(String::as_str).collect();
+        if let Some((name, oid)) = restack::find_merge(repo, &stack, &names)? {
+            return Err(restack::merge_refusal("modify", &name, oid));
+        }
+    }
+
+    if options.new_commit && options.message.is_none() {
+        return Err(GxError::InvalidState(
+            "Pass the message of the new commit with -m.".to_string(),
+        ));
+    }
+    if !options.new_commit && branch.tip == branch.base {
+        return Err(GxError::InvalidState(format!(
+            "Branch {head_branch} has no commits of its own to amend. Pass --commit to add one."
+        )));
+    }
+
+    // With -a the changes are only staged on disk once nothing can fail any
+    // more, so a refused modify leaves the index as it was.
+    let mut index = repo.index()?;
+    if options.all {
+        index.update_all(["*"], None)?;
+    }
+    let staged = repo.diff_tree_to_index(Some(&head_commit.tree()?), Some(&index), None)?;
+    let has_staged = staged.deltas().len() > 0;
+    if options.new_commit && !has_staged {
+        return Err(GxError::InvalidState(
+            "No staged changes to commit. Stage changes with `git add` or pass -a.".to_string(),
+        ));
+    }
+    if !options.new_commit && !has_staged && options.message.is_none() {
+        return Err(GxError::InvalidState(
+            "Nothing to modify. Stage changes with `git add`, pass -a, or pass -m to reword the last commit.".to_string(),
+        ));
+    }
+    if !descendants.is_empty()
+        && repo
+            .diff_index_to_workdir(Some(&index), None)?
+            .deltas()
+            .len()
+            > 0
+    {
+        return Err(GxError::InvalidState(
+            "You have unstaged changes, which restacking the branches above would overwrite. Stage them, pass -a or stash them first.".to_string(),
+        ));
+    }
+
+    if options.all {
+        index.write()?;
+    }
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let signature = repo.signature()?;
+    let new_tip = if options.new_commit {
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            options.message.unwrap_or_default(),
+            &tree,
+            &[&head_commit],
+        )?
+    } else {
+        head_commit.amend(
+            Some("HEAD"),
+            None,
+            Some(&signature),
+            None,
+            options.message,
+            Some(&tree),
+        )?
+    };
+    println!(
+        "{} {} ({} -> {})",
+        if options.new_commit {
+            "Committed to"
+        } else {
+            "Amended"
+        }
+        .green()
+        .bold(),
+        head_branch.yellow().bold(),
+        &head_commit.id().to_string()[0..7],
+        &new_tip.to_string()[0..7],
+    );
+
+    if descendants.is_empty() {
+        return Ok(());
+    }
+    // Keep the new commit if the restack is aborted; only the branches above
+    // are put back.
+    for b in stack.branches.iter_mut() {
+        if b.name == head_branch {
+            b.tip = new_tip;
+        }
+    }
+    let op = restack::start_operation(repo, "modify", &stack, &head_branch, descendants)?;
+    report::restack(restack::run_operation(repo, op)?)
+}
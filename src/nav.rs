@@ -0,0 +1,81 @@
+use crate::prompt;
+use colored::Colorize;
+use git2::Repository;
+use gx::checkout::checkout_branch;
+use gx::error::GxError;
+use gx::stack::Stack;
+
+/// Moves up to `steps` branches towards the top of the stack.
+pub fn up(repo: &Repository, trunk_name: Option<&str>, steps: usize) -> Result<(), GxError> {
+    let (stack, start) = Stack::current(repo, trunk_name, "first")?;
+
+    let mut curr = start.clone();
+    for _ in 0..steps {
+        match pick_child(&stack, &curr)? {
+            Some(child) => curr = child,
+            None => break,
+        }
+    }
+    switch_to(repo, &start, &curr, "top")
+}
+
+/// Moves up to `steps` branches towards the bottom of the stack.
+pub fn down(repo: &Repository, trunk_name: Option<&str>, steps: usize) -> Result<(), GxError> {
+    let (stack, start) = Stack::current(repo, trunk_name, "first")?;
+
+    let mut curr = start.clone();
+    for _ in 0..steps {
+        match stack.find(&curr).and_then(|b| stack.find(&b.parent)) {
+            Some(parent) => curr = parent.name.clone(),
+            None => break,
+        }
+    }
+    switch_to(repo, &start, &curr, "bottom")
+}
+
+/// Moves to the topmost branch of the stack.
+pub fn top(repo: &Repository, trunk_name: Option<&str>) -> Result<(), GxError> {
+    let (stack, start) = Stack::current(repo, trunk_name, "first")?;
+
+    let mut curr = start.clone();
+    while let Some(child) = pick_child(&stack, &curr)? {
+        curr = child;
+    }
+    switch_to(repo, &start, &curr, "top")
+}
+
+/// Moves to the bottommost branch of the stack.
+pub fn bottom(repo: &Repository, trunk_name: Option<&str>) -> Result<(), GxError> {
+    let (stack, start) = Stack::current(repo, trunk_name, "first")?;
+
+    let bottom = stack.branches[0].name.clone();
+    switch_to(repo, &start, &bottom, "bottom")
+}
+
+/// Returns the child of `name` to move to, asking the user when there are several.
+fn pick_child(stack: &Stack, name: &str) -> Result<Option<String>, GxError> {
+    let children: Vec<String> = stack
+        .children(name)
+        .iter()
+        .map(|b| b.name.clone())
+        .collect();
+    match children.len() {
+        0 => Ok(None),
+        1 => Ok(Some(children[0].clone())),
+        _ => {
+            let question = format!("Branch {} has multiple children:", name.yellow().bold());
+            let choice = prompt::choose(&question, &children)?;
+            Ok(Some(children[choice].clone()))
+        }
+    }
+}
+
+fn switch_to(repo: &Repository, from: &str, to: &str, end: &str) -> Result<(), GxError> {
+    if from == to {
+        println!("Already at the {end} of the stack.");
+        return Ok(());
+    }
+    checkout_branch(repo, to)?;
+    println!("Switched to branch {}.", to.yellow().bold());
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use gx::error::GxError;
+use std::io::{self, BufRead, Write};
+
+/// Asks the user to pick one of `options` and returns its index.
+pub fn choose(question: &str, options: &[String]) -> Result<usize, GxError> {
+    println!("{question}");
+    for (i, option) in options.iter().enumerate() {
+        println!("  {}) {}", i + 1, option);
+    }
+    print!("Choose [1-{}]: ", options.len());
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    match answer.trim().parse::<usize>() {
+        Ok(n) if (1..=options.len()).contains(&n) => Ok(n - 1),
+        _ => Err(format!("Invalid choice: {}", answer.trim()).into()),
+    }
+}
+
+/// Asks a yes/no question. Anything but an explicit yes counts as no.
+pub fn confirm(question: &str) -> Result<bool, GxError> {
+    print!("{question} [y/N]: ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,136 @@
+use crate::error::GxError;
+use crate::remote;
+use crate::stack::Stack;
+use git2::{BranchType, ErrorCode, Oid, PushOptions, Repository};
+use std::cell::RefCell;
+
+/// What happened to a single branch during a push.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushOutcome {
+    Created,
+    Updated,
+    Unchanged,
+    /// The remote refused the push, or it would have overwritten commits we
+    /// have not fetched.
+    Rejected(String),
+}
+
+/// A branch of a pushed stack.
+#[derive(Debug)]
+pub struct PushedBranch {
+    pub name: String,
+    /// Remote-tracking name of the branch pushed to, e.g. `origin/feature-a`.
+    pub target: String,
+    pub outcome: PushOutcome,
+}
+
+/// Force-pushes every branch of the current stack to its upstream.
+pub fn push(repo: &Repository, trunk_name: Option<&str>) -> Result<Vec<PushedBranch>, GxError> {
+    let (stack, _) = Stack::current(repo, trunk_name, "to push it")?;
+    push_stack(repo, &stack)
+}
+
+/// Pushes the branches of `stack` parents first. A rejected branch does not
+/// stop the others from being pushed.
+pub fn push_stack(repo: &Repository, stack: &Stack) -> Result<Vec<PushedBranch>, GxError> {
+    let default_remote = remote::trunk_remote(repo, &stack.trunk);
+    let mut pushed = Vec::new();
+    for b in &stack.branches {
+        let (outcome, target) = push_branch(repo, &b.name, &default_remote)?;
+        pushed.push(PushedBranch {
+            name: b.name.clone(),
+            target,
+            outcome,
+        });
+    }
+    Ok(pushed)
+}
+
+/// Force-pushes `name` to its upstream, or to a branch of the same name on
+/// `default_remote` which then becomes its upstream.
+///
+/// The push only goes through if the remote branch still points where our
+/// remote-tracking branch says it does, like `git push --force-with-lease`.
+/// Returns the outcome and the remote-tracking name of the target.
+fn push_branch(
+    repo: &Repository,
+    name: &str,
+    default_remote: &str,
+) -> Result<(PushOutcome, String), GxError> {
+    let local_ref = format!("refs/heads/{name}");
+    let tip = repo
+        .find_branch(name, BranchType::Local)?
+        .get()
+        .peel_to_commit()?
+        .id();
+
+    let config = repo.config()?;
+    let (remote_name, remote_ref, has_upstream) = match (
+        repo.branch_upstream_remote(&local_ref),
+        config.get_string(&format!("branch.{name}.merge")),
+    ) {
+        (Ok(remote), Ok(merge)) => (
+            remote.as_str().unwrap_or(default_remote).to_string(),
+            merge,
+            true,
+        ),
+        _ => (default_remote.to_string(), local_ref.clone(), false),
+    };
+    let remote_branch = remote_ref
+        .strip_prefix("refs/heads/")
+        .unwrap_or(&remote_ref);
+    let tracking_ref = format!("refs/remotes/{remote_name}/{remote_branch}");
+    let target = format!("{remote_name}/{remote_branch}");
+
+    let expected = match repo.refname_to_id(&tracking_ref) {
+        Ok(oid) => Some(oid),
+        Err(e) if e.code() == ErrorCode::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    if expected == Some(tip) {
+        return Ok((PushOutcome::Unchanged, target));
+    }
+
+    let rejection = RefCell::new(None);
+    let mut callbacks = remote::callbacks(&config);
+    callbacks.push_negotiation(|updates| {
+        for update in updates {
+            if update.src() != expected.unwrap_or_else(Oid::zero) {
+                *rejection.borrow_mut() =
+                    Some("remote has changed since the last fetch".to_string());
+                return Err(git2::Error::from_str("stale info"));
+            }
+        }
+        Ok(())
+    });
+    callbacks.push_update_reference(|_, status| {
+        if let Some(status) = status {
+            *rejection.borrow_mut() = Some(status.to_string());
+        }
+        Ok(())
+    });
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks);
+
+    let refspec = format!("+{local_ref}:{remote_ref}");
+    let mut remote = repo.find_remote(&remote_name)?;
+    let result = remote.push(&[refspec.as_str()], Some(&mut options));
+    drop(options);
+    if let Some(reason) = rejection.into_inner() {
+        return Ok((PushOutcome::Rejected(reason), target));
+    }
+    if let Err(e) = result {
+        return Ok((PushOutcome::Rejected(e.message().to_string()), target));
+    }
+
+    repo.reference(&tracking_ref, tip, true, "gx: push")?;
+    if !has_upstream {
+        repo.find_branch(name, BranchType::Local)?
+            .set_upstream(Some(&target))?;
+    }
+    let outcome = match expected {
+        Some(_) => PushOutcome::Updated,
+        None => PushOutcome::Created,
+    };
+    Ok((outcome, target))
+}
@@ -0,0 +1,55 @@
+use crate::trunk::Trunk;
+use git2::{Config, Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository};
+
+/// Remote used when the trunk does not say otherwise.
+const DEFAULT_REMOTE: &str = "origin";
+
+/// Callbacks that authenticate the way the git CLI does: through the SSH agent
+/// for SSH remotes and through the configured credential helpers otherwise.
+pub fn callbacks(config: &Config) -> RemoteCallbacks<'_> {
+    let mut attempts = 0;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        // libgit2 keeps asking as long as credentials are handed back.
+        attempts += 1;
+        if attempts > 3 {
+            return Err(git2::Error::from_str("Authentication failed."));
+        }
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            return Cred::credential_helper(config, url, username);
+        }
+        Cred::default()
+    });
+    callbacks
+}
+
+/// Name of the remote the trunk is fetched from.
+pub fn trunk_remote(repo: &Repository, trunk: &Trunk) -> String {
+    let local_ref = format!("refs/heads/{}", trunk.name);
+    if let Ok(remote) = repo.branch_upstream_remote(&local_ref) {
+        if let Some(remote) = remote.as_str() {
+            return remote.to_string();
+        }
+    }
+    for remote in repo.remotes().iter().flatten().flatten() {
+        if trunk.name.starts_with(&format!("{remote}/")) {
+            return remote.to_string();
+        }
+    }
+    DEFAULT_REMOTE.to_string()
+}
+
+/// Fetches every branch of `remote_name` using its configured refspecs.
+pub fn fetch(repo: &Repository, remote_name: &str) -> Result<(), git2::Error> {
+    let config = repo.config()?;
+    let mut remote = repo.find_remote(remote_name)?;
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks(&config));
+    remote.fetch(&[] as &[&str], Some(&mut options), None)
+}
@@ -0,0 +1,278 @@
+use crate::date::{self, DateFormat};
+use colored::Colorize;
+use git2::{Commit, Oid, Repository};
+use gx::error::GxError;
+use gx::meta;
+use gx::stack::{self, Stack};
+use gx::status::{self, BranchStatus};
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const NODE: &str = "\u{25ef}";
+const CURRENT_NODE: &str = "\u{25c9}";
+const LINE: &str = "\u{2502}";
+
+/// Draws `stack` as a tree with the trunk at the bottom.
+///
+/// Every branch is a node followed by its own commits. A branch with several
+/// children gets one column per child subtree, joined back together right above
+/// the branch:
+///
+/// ```text
+///   ◯ feature-d
+///   │ 0a1b2c3 - d (2 hours ago) <A>
+/// ◉ │ feature-c
+/// │ │ 5eeb6f8 - c (3 hours ago) <A>
+/// ├─╯
+/// ◯ feature-b
+/// │ a68c623 - b (2 days ago) <A>
+/// ◯ main
+/// ```
+///
+/// With a detached HEAD, `head` is the commit it points at and its line is
+/// marked instead of a branch. Commit lines also name any other branches that
+/// point at the commit.
+pub fn render_stack(
+    repo: &Repository,
+    stack: &Stack,
+    current: &str,
+    head: Option<Oid>,
+    date: DateFormat,
+) -> Result<Vec<String>, GxError> {
+    let mut branches_at: HashMap<Oid, Vec<String>> = HashMap::new();
+    for (oid, at_commit) in stack::get_local_branches(repo)? {
+        for b in at_commit {
+            if let Some(name) = b.name()? {
+                branches_at.entry(oid).or_default().push(name.to_string());
+            }
+        }
+    }
+
+    let mut renderer = Renderer {
+        repo,
+        stack,
+        current,
+        head,
+        date,
+        branches_at,
+        lines: Vec::new(),
+    };
+    renderer.subtree(&stack.trunk.name, 0, &[])?;
+    Ok(renderer.lines)
+}
+
+/// One line describing `commit`, in the style of `git log --oneline`. Merge
+/// commits are tagged so they stand out in first-parent history.
+pub fn format_commit(commit: &Commit, date: DateFormat) -> String {
+    let commit_hash = &commit.id().to_string()[0..7];
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    let commit_desc = commit.summary().unwrap_or("<no summary>");
+    let commit_time = date::format_time(commit.author().when(), date, now);
+    let commit_author = commit.author().name().unwrap_or("Unknown").bold();
+    let merge = if commit.parent_count() > 1 {
+        format!(" {}", "[merge]".magenta().bold())
+    } else {
+        String::new()
+    };
+
+    format!(
+        "{}{} - {} {} {}",
+        commit_hash.red().bold(),
+        merge,
+        commit_desc.bold(),
+        format!("({})", commit_time).green().bold(),
+        format!("<{}>", commit_author).blue().bold(),
+    )
+}
+
+struct Renderer<'a> {
+    repo: &'a Repository,
+    stack: &'a Stack,
+    current: &'a str,
+    head: Option<Oid>,
+    date: DateFormat,
+    /// Names of the local branches pointing at each commit, in name order.
+    branches_at: HashMap<Oid, Vec<String>>,
+    lines: Vec<String>,
+}
+
+impl Renderer<'_> {
+    /// Renders `name` and everything stacked on it, with `name` in column `col`.
+    /// `active` holds the columns to the right whose lines run past this subtree.
+    fn subtree(&mut self, name: &str, col: usize, active: &[usize]) -> Result<(), GxError> {
+        let children: Vec<String> = self
+            .stack
+            .children(name)
+            .iter()
+            .map(|b| b.name.clone())
+            .collect();
+
+        let mut starts = Vec::new();
+        let mut next = col;
+        for child in &children {
+            starts.push(next);
+            next += self.width(child);
+        }
+
+        // Later children go further right and are drawn first, so their lines
+        // have to keep running down past the earlier ones.
+        for (i, child) in children.iter().enumerate().rev() {
+            let mut child_active = starts[i + 1..].to_vec();
+            child_active.extend_from_slice(active);
+            self.subtree(child, starts[i], &child_active)?;
+        }
+        if starts.len() > 1 {
+            self.lines.push(join_line(&starts, active));
+        }
+
+        self.node(name, col, active)
+    }
+
+    fn node(&mut self, name: &str, col: usize, active: &[usize]) -> Result<(), GxError> {
+        let branch = match self.stack.find(name) {
+            Some(b) => b,
+            None => {
+                let label = name.green().bold().to_string();
+                self.lines
+                    .push(format!("{}{label}", prefix(col, active, NODE)));
+                return Ok(());
+            }
+        };
+
+        let (glyph, label) = if name == self.current {
+            (CURRENT_NODE, name.cyan().bold().to_string())
+        } else {
+            (NODE, name.yellow().bold().to_string())
+        };
+        let pr = meta::read_pr(self.repo, name)?
+            .map(|n| format!(" #{n}").magenta().to_string())
+            .unwrap_or_default();
+        let status = status_label(&status::branch_status(self.repo, self.stack, branch)?);
+        self.lines
+            .push(format!("{}{label}{pr}{status}", prefix(col, active, glyph)));
+
+        for oid in self.stack.commits(self.repo, branch)? {
+            let commit = self.repo.find_commit(oid)?;
+            let mut line = format_commit(&commit, self.date);
+            let others: Vec<&str> = self
+                .branches_at
+                .get(&oid)
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .filter(|&n| n != name)
+                .collect();
+            if !others.is_empty() {
+                line += &format!(" ({})", others.join(", ")).dimmed().to_string();
+            }
+            if self.head == Some(oid) {
+                self.lines.push(format!(
+                    "{}{line} {}",
+                    prefix(col, active, CURRENT_NODE),
+                    "<- HEAD".cyan().bold()
+                ));
+            } else {
+                self.lines
+                    .push(format!("{}{line}", prefix(col, active, LINE)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of columns the subtree rooted at `name` needs.
+    fn width(&self, name: &str) -> usize {
+        let children = self.stack.children(name);
+        if children.is_empty() {
+            return 1;
+        }
+        children.iter().map(|c| self.width(&c.name)).sum()
+    }
+}
+
+/// Upstream, ahead/behind counts and flags shown after a branch name, e.g.
+/// ` (origin/feature-a ↑2 ↓1) [needs push]`.
+fn status_label(status: &BranchStatus) -> String {
+    let mut label = String::new();
+    if let Some(upstream) = &status.upstream {
+        let mut counts = String::new();
+        if let Some(ahead) = status.ahead.filter(|&n| n > 0) {
+            counts += &format!(" \u{2191}{ahead}");
+        }
+        if let Some(behind) = status.behind.filter(|&n| n > 0) {
+            counts += &format!(" \u{2193}{behind}");
+        }
+        label += &format!(" ({upstream}{counts})").dimmed().to_string();
+    }
+
+    let mut flags = Vec::new();
+    if status.upstream_gone {
+        flags.push("upstream gone".red().bold());
+    }
+    if status.needs_push {
+        flags.push("needs push".yellow());
+    }
+    if status.needs_restack {
+        flags.push("needs restack".yellow());
+    }
+    for flag in flags {
+        label += &format!(" [{flag}]");
+    }
+    label
+}
+
+/// Graph cells for a line with `glyph` in column `col` and a vertical line in
+/// every `active` column.
+fn prefix(col: usize, active: &[usize], glyph: &str) -> String {
+    let last = active.iter().copied().chain([col]).max().unwrap_or(col);
+    let mut cells = String::new();
+    for c in 0..=last {
+        if c == col {
+            cells += glyph;
+        } else if active.contains(&c) {
+            cells += LINE;
+        } else {
+            cells += " ";
+        }
+        cells += " ";
+    }
+    cells
+}
+
+/// The line joining sibling subtrees starting at `starts` into their parent
+/// in column `starts[0]`.
+fn join_line(starts: &[usize], active: &[usize]) -> String {
+    let first = starts[0];
+    let last_start = *starts.last().unwrap();
+    let last = active
+        .iter()
+        .copied()
+        .chain([last_start])
+        .max()
+        .unwrap_or(last_start);
+
+    let mut cells = String::new();
+    for c in first..=last {
+        let (cell, fill) = if c == first {
+            ("\u{251c}", "\u{2500}")
+        } else if c == last_start {
+            ("\u{256f}", " ")
+        } else if starts.contains(&c) {
+            ("\u{2534}", "\u{2500}")
+        } else if c < last_start {
+            ("\u{2500}", "\u{2500}")
+        } else if active.contains(&c) {
+            (LINE, " ")
+        } else {
+            (" ", " ")
+        };
+        cells += cell;
+        cells += fill;
+    }
+    format!("{}{}", "  ".repeat(first), cells.trim_end())
+}
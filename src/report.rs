@@ -0,0 +1,97 @@
+use colored::Colorize;
+use gx::error::GxError;
+use gx::push::{PushOutcome, PushedBranch};
+use gx::restack::{Outcome, Report};
+use gx::state::Operation;
+use gx::sync::{Plan, TrunkUpdate};
+use std::io::{self, Write};
+
+const RESOLVE_HINT: &str = "Resolve the conflicts, stage them with `git add` and run `gx stack continue`, or run `gx stack abort`.";
+
+/// Prints a line per restacked branch. A restack that stopped on a conflict
+/// becomes a [`GxError::Conflict`] explaining how to go on.
+pub fn restack(report: Report) -> Result<(), GxError> {
+    for (name, outcome) in &report.branches {
+        match outcome {
+            Outcome::Moved { from, to } => println!(
+                "{} {} ({} -> {})",
+                "Restacked".green().bold(),
+                name.yellow().bold(),
+                &from.to_string()[0..7],
+                &to.to_string()[0..7],
+            ),
+            Outcome::UpToDate => println!("{} is already up to date.", name.yellow().bold()),
+        }
+    }
+    match report.conflict {
+        Some(name) => Err(GxError::Conflict(format!(
+            "Restacking {} hit conflicts. {RESOLVE_HINT}",
+            name.yellow().bold()
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub fn aborted(op: &Operation) {
+    println!("Aborted the {}. All branches were restored.", op.kind);
+}
+
+/// Writes how to resume or abandon the stopped operation to `out`.
+pub fn write_in_progress(out: &mut impl Write, op: &Operation) -> io::Result<()> {
+    let branch = op.pending.first().map(String::as_str).unwrap_or_default();
+    writeln!(
+        out,
+        "{} A {} stopped while restacking {}. {RESOLVE_HINT}",
+        "Note:".yellow().bold(),
+        op.kind,
+        branch.yellow().bold(),
+    )
+}
+
+/// Prints what fetching the trunk found, before the user is asked about the
+/// merged branches.
+pub fn sync_plan(plan: &Plan) {
+    println!("Fetched {}.", plan.remote.bold());
+    let trunk = &plan.stack.trunk.name;
+    match plan.trunk_update {
+        TrunkUpdate::Unchanged => {}
+        TrunkUpdate::FastForwarded(oid) => println!(
+            "Fast-forwarded {} to {}.",
+            trunk.green().bold(),
+            &oid.to_string()[0..7]
+        ),
+        TrunkUpdate::Diverged => eprintln!(
+            "Warning: {} has diverged from its upstream and was not updated.",
+            trunk.green().bold()
+        ),
+    }
+    if !plan.merged.is_empty() {
+        println!(
+            "These branches have been merged into {}: {}",
+            trunk.green().bold(),
+            plan.merged.join(", ").yellow().bold(),
+        );
+    }
+}
+
+/// Prints a line per pushed branch. Returns `false` if any was rejected.
+pub fn push(pushed: &[PushedBranch]) -> bool {
+    let mut all_pushed = true;
+    for b in pushed {
+        let name = b.name.yellow().bold();
+        match &b.outcome {
+            PushOutcome::Created => {
+                println!("{:<10} {} -> {}", "created".green().bold(), name, b.target)
+            }
+            PushOutcome::Updated => {
+                println!("{:<10} {} -> {}", "updated".green().bold(), name, b.target)
+            }
+            PushOutcome::Unchanged => println!("{:<10} {}", "unchanged".dimmed(), name),
+            PushOutcome::Rejected(reason) => {
+                all_pushed = false;
+                println!("{:<10} {} ({})", "rejected".red().bold(), name, reason)
+            }
+        }
+    }
+    all_pushed
+}
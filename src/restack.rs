@@ -0,0 +1,423 @@
+use crate::checkout::{checkout_branch, has_uncommitted_changes};
+use crate::error::GxError;
+use crate::meta::{self, BranchMeta};
+use crate::stack::Stack;
+use crate::state::{self, Operation, OriginalBranch};
+use crate::trunk::{self, Trunk};
+use git2::{build::CheckoutBuilder, BranchType, ErrorCode, Oid, Rebase, Repository};
+use std::collections::HashSet;
+
+/// What happened to a single branch during a restack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Moved { from: Oid, to: Oid },
+    UpToDate,
+}
+
+/// What a restack did before it finished or stopped on a conflict.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Branches restacked so far, in order, with what happened to each.
+    pub branches: Vec<(String, Outcome)>,
+    /// Branch the restack stopped on with conflicts. The operation is saved
+    /// and waits for [`continue_operation`], [`skip_commit`] or
+    /// [`abort_operation`].
+    pub conflict: Option<String>,
+}
+
+/// Refuses to start a command while a restack it could interfere with is
+/// stopped on a conflict.
+pub fn ensure_no_operation(repo: &Repository) -> Result<(), GxError> {
+    match state::read(repo)? {
+        Some(op) => Err(GxError::InvalidState(format!(
+            "A {} is already in progress. Run `gx stack continue` or `gx stack abort` first.",
+            op.kind
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Rebases every branch of the current stack whose parent has moved onto the
+/// parent's new tip, parents first.
+///
+/// Rebasing cannot keep merge commits, so branches that would be rewritten
+/// and contain one are refused unless `linearize` is set, in which case the
+/// merges are dropped and the commits they brought in are replayed instead.
+pub fn restack(
+    repo: &Repository,
+    trunk_name: Option<&str>,
+    linearize: bool,
+) -> Result<Report, GxError> {
+    ensure_no_operation(repo)?;
+    let (stack, head_branch) = Stack::current(repo, trunk_name, "to restack it")?;
+    if has_uncommitted_changes(repo)? {
+        return Err(GxError::InvalidState(
+            "You have uncommitted changes. Commit or stash them before restacking.".to_string(),
+        ));
+    }
+
+    let branches: Vec<String> = stack
+        .branches
+        .iter()
+        .filter(|b| b.parent != stack.trunk.name)
+        .map(|b| b.name.clone())
+        .collect();
+
+    if !linearize {
+        let mut moving: HashSet<&str> = HashSet::new();
+        for b in &stack.branches {
+            if b.parent == stack.trunk.name {
+                continue;
+            }
+            let parent_tip = stack.find(&b.parent).map_or(stack.trunk.oid, |p| p.tip);
+            if b.base != parent_tip || moving.contains(b.parent.as_str()) {
+                moving.insert(&b.name);
+            }
+        }
+        let moving: Vec<&str> = moving.into_iter().collect();
+        if let Some((name, oid)) = find_merge(repo, &stack, &moving)? {
+            return Err(merge_refusal("restack", &name, oid));
+        }
+    }
+
+    let op = start_operation(repo, "restack", &stack, &head_branch, branches)?;
+    run_operation(repo, op)
+}
+
+/// Prepares an operation that restacks `branches`, which must list parents
+/// before their children.
+///
+/// The current parent and base of every branch in the stack are recorded as
+/// metadata first, so the stack keeps its shape while branches are rewritten.
+pub fn start_operation(
+    repo: &Repository,
+    kind: &str,
+    stack: &Stack,
+    head: &str,
+    branches: Vec<String>,
+) -> Result<Operation, GxError> {
+    let original = original_branches(repo, stack)?;
+    record_stack(repo, stack)?;
+
+    Ok(Operation {
+        kind: kind.to_string(),
+        trunk: stack.trunk.name.clone(),
+        head: head.to_string(),
+        original,
+        pending: branches,
+    })
+}
+
+/// The tips and metadata of the branches of `stack` as they are now, which
+/// aborting an operation puts back.
+///
+/// Commands that rewrite branches before restacking the rest take this first
+/// and hand it to the operation in place of what [`start_operation`] records.
+pub fn original_branches(repo: &Repository, stack: &Stack) -> Result<Vec<OriginalBranch>, GxError> {
+    let mut original = Vec::new();
+    for b in &stack.branches {
+        original.push(OriginalBranch {
+            name: b.name.clone(),
+            tip: b.tip,
+            meta: meta::read(repo, &b.name)?,
+        });
+    }
+    Ok(original)
+}
+
+/// Records the parent and base of every branch in `stack` as metadata, for
+/// commands that reshape a stack before restacking it.
+pub fn record_stack(repo: &Repository, stack: &Stack) -> Result<(), GxError> {
+    for b in &stack.branches {
+        meta::write(
+            repo,
+            &b.name,
+            &BranchMeta {
+                parent: b.parent.clone(),
+                base: b.base,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Restacks the pending branches of `op` one at a time, stopping at the first
+/// branch that hits a conflict.
+///
+/// The operation is saved before each branch is rebased, so whatever stops
+/// it, a conflict or any other error, it can be continued or aborted later.
+pub fn run_operation(repo: &Repository, op: Operation) -> Result<Report, GxError> {
+    run_pending(repo, op, Report::default())
+}
+
+/// Commits the resolved conflict of the stopped operation and carries on with
+/// the remaining branches.
+pub fn continue_operation(repo: &Repository) -> Result<Report, GxError> {
+    let mut op = state::read(repo)?.ok_or_else(no_operation)?;
+    let mut report = Report::default();
+    if let Some(mut rebase) = open_rebase(repo)? {
+        if repo.index()?.has_conflicts() {
+            return Err(GxError::Conflict("There are still unresolved conflicts. Resolve them and stage the files with `git add` before continuing.".to_string()));
+        }
+        let signature = repo.signature()?;
+        match rebase.commit(None, &signature, None) {
+            Ok(_) => {}
+            Err(e) if e.code() == ErrorCode::Applied => {}
+            Err(e) => return Err(e.into()),
+        }
+        resume_rebase(repo, &mut op, rebase, &mut report)?;
+    }
+    run_pending(repo, op, report)
+}
+
+/// Drops the commit that caused the conflict and carries on with the remaining
+/// commits and branches.
+pub fn skip_commit(repo: &Repository) -> Result<Report, GxError> {
+    let mut op = state::read(repo)?.ok_or_else(no_operation)?;
+    let mut report = Report::default();
+    if let Some(rebase) = open_rebase(repo)? {
+        // A hard reset would also clean up the repository state, deleting the
+        // rebase before it can carry on, so the index and working tree are
+        // put back to HEAD by hand.
+        let head = repo.head()?.peel_to_commit()?;
+        let mut index = repo.index()?;
+        index.read_tree(&head.tree()?)?;
+        index.write()?;
+        repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+        resume_rebase(repo, &mut op, rebase, &mut report)?;
+    }
+    run_pending(repo, op, report)
+}
+
+/// Abandons the stopped operation and puts every branch it touched back where
+/// it was before the operation started. Returns the abandoned operation.
+pub fn abort_operation(repo: &Repository) -> Result<Operation, GxError> {
+    let op = state::read(repo)?.ok_or_else(no_operation)?;
+    if let Some(mut rebase) = open_rebase(repo)? {
+        rebase.abort()?;
+    }
+
+    for b in &op.original {
+        let ref_name = format!("refs/heads/{}", b.name);
+        repo.reference(&ref_name, b.tip, true, &format!("gx: abort {}", op.kind))?;
+        match &b.meta {
+            Some(m) => meta::write(repo, &b.name, m)?,
+            None => meta::remove(repo, &b.name)?,
+        }
+    }
+
+    let branch_ref = repo
+        .find_branch(&op.head, BranchType::Local)?
+        .into_reference();
+    let tree = branch_ref.peel_to_tree()?;
+    repo.checkout_tree(tree.as_object(), Some(CheckoutBuilder::new().force()))?;
+    repo.set_head(branch_ref.name().unwrap_or_default())?;
+    state::remove(repo)?;
+    Ok(op)
+}
+
+/// The first merge commit among the own commits of the branches `names`,
+/// together with the branch it is on.
+pub fn find_merge(
+    repo: &Repository,
+    stack: &Stack,
+    names: &[&str],
+) -> Result<Option<(String, Oid)>, git2::Error> {
+    for b in &stack.branches {
+        if !names.contains(&b.name.as_str()) {
+            continue;
+        }
+        for oid in stack.commits(repo, b)? {
+            if repo.find_commit(oid)?.parent_count() > 1 {
+                return Ok(Some((b.name.clone(), oid)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Explains that `kind` would have to rewrite merge commit `oid` on `name`.
+pub fn merge_refusal(kind: &str, name: &str, oid: Oid) -> GxError {
+    GxError::UnsupportedHistory(format!(
+        "Branch {name} contains merge commit {}, which a {kind} cannot keep. Run `gx stack {kind} --linearize` to rebase the merge away and replay the commits it brought in.",
+        &oid.to_string()[0..7],
+    ))
+}
+
+fn no_operation() -> GxError {
+    GxError::InvalidState("No gx operation is in progress.".to_string())
+}
+
+/// Restacks the pending branches of `op`, adding what happened to `report`.
+fn run_pending(
+    repo: &Repository,
+    mut op: Operation,
+    mut report: Report,
+) -> Result<Report, GxError> {
+    if report.conflict.is_some() {
+        return Ok(report);
+    }
+    let trunk = trunk::find_trunk(repo, Some(&op.trunk))?;
+    while let Some(name) = op.pending.first().cloned() {
+        state::write(repo, &op)?;
+        let meta = meta::read(repo, &name)?
+            .ok_or_else(|| format!("Branch {name} has no recorded parent."))?;
+        match restack_branch(repo, &trunk, &name, &meta)? {
+            Some(outcome) => report.branches.push((name, outcome)),
+            None => {
+                report.conflict = Some(name);
+                return Ok(report);
+            }
+        }
+        op.pending.remove(0);
+    }
+    finish_operation(repo, &op)?;
+    Ok(report)
+}
+
+/// Applies the remaining commits of an interrupted rebase of `op`'s first
+/// pending branch, adding the branch to `report`, or recording it as the
+/// conflict if the rebase stopped on another one.
+fn resume_rebase(
+    repo: &Repository,
+    op: &mut Operation,
+    mut rebase: Rebase,
+    report: &mut Report,
+) -> Result<(), GxError> {
+    let trunk = trunk::find_trunk(repo, Some(&op.trunk))?;
+    let name = op
+        .pending
+        .first()
+        .cloned()
+        .ok_or("The saved operation has no pending branch.")?;
+    let meta =
+        meta::read(repo, &name)?.ok_or_else(|| format!("Branch {name} has no recorded parent."))?;
+
+    if !apply_commits(repo, &mut rebase)? {
+        report.conflict = Some(name);
+        return Ok(());
+    }
+    let to = finish_rebase(repo, &trunk, &name, &meta, rebase)?;
+    let from = op
+        .original
+        .iter()
+        .find(|b| b.name == name)
+        .map(|b| b.tip)
+        .unwrap_or(to);
+    report.branches.push((name, Outcome::Moved { from, to }));
+    op.pending.remove(0);
+    Ok(())
+}
+
+fn finish_operation(repo: &Repository, op: &Operation) -> Result<(), GxError> {
+    state::remove(repo)?;
+    // An empty head means there was no branch left to return to.
+    if !op.head.is_empty() {
+        checkout_branch(repo, &op.head)?;
+    }
+    Ok(())
+}
+
+/// Rebases the commits of `name` after `meta.base` onto the current tip of its
+/// parent, and records the new base. Returns `None` if the rebase stopped on a
+/// conflict.
+fn restack_branch(
+    repo: &Repository,
+    trunk: &Trunk,
+    name: &str,
+    meta: &BranchMeta,
+) -> Result<Option<Outcome>, GxError> {
+    let parent_tip = branch_tip(repo, trunk, &meta.parent)?;
+    let branch_ref = repo.find_branch(name, BranchType::Local)?.into_reference();
+    let from = branch_ref.peel_to_commit()?.id();
+
+    if meta.base == parent_tip {
+        return Ok(Some(Outcome::UpToDate));
+    }
+
+    let branch = repo.reference_to_annotated_commit(&branch_ref)?;
+    let upstream = repo.find_annotated_commit(meta.base)?;
+    let onto = repo.find_annotated_commit(parent_tip)?;
+    let mut rebase = repo.rebase(Some(&branch), Some(&upstream), Some(&onto), None)?;
+
+    if !apply_commits(repo, &mut rebase)? {
+        return Ok(None);
+    }
+    let to = finish_rebase(repo, trunk, name, meta, rebase)?;
+    Ok(Some(Outcome::Moved { from, to }))
+}
+
+/// Applies and commits the remaining operations of `rebase`. Returns `false`
+/// if an operation left conflicts in the index.
+///
+/// Any other failure drops the rebase, leaving the branch where it was, so
+/// continuing the operation restacks that branch from scratch instead of
+/// resuming past the commit that failed.
+fn apply_commits(repo: &Repository, rebase: &mut Rebase) -> Result<bool, git2::Error> {
+    replay_commits(repo, rebase).or_else(|e| {
+        repo.cleanup_state()?;
+        Err(e)
+    })
+}
+
+fn replay_commits(repo: &Repository, rebase: &mut Rebase) -> Result<bool, git2::Error> {
+    let signature = repo.signature()?;
+    while let Some(op) = rebase.next() {
+        op?;
+        if repo.index()?.has_conflicts() {
+            return Ok(false);
+        }
+        match rebase.commit(None, &signature, None) {
+            Ok(_) => {}
+            // The change is already part of the new parent.
+            Err(e) if e.code() == ErrorCode::Applied => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Moves the branch to the rebased commits and records its parent's tip as the
+/// new base. Returns the branch's new tip.
+fn finish_rebase(
+    repo: &Repository,
+    trunk: &Trunk,
+    name: &str,
+    meta: &BranchMeta,
+    mut rebase: Rebase,
+) -> Result<Oid, GxError> {
+    rebase.finish(Some(&repo.signature()?))?;
+    meta::write(
+        repo,
+        name,
+        &BranchMeta {
+            parent: meta.parent.clone(),
+            base: branch_tip(repo, trunk, &meta.parent)?,
+        },
+    )?;
+    Ok(repo
+        .find_branch(name, BranchType::Local)?
+        .get()
+        .peel_to_commit()?
+        .id())
+}
+
+fn open_rebase(repo: &Repository) -> Result<Option<Rebase<'_>>, git2::Error> {
+    match repo.open_rebase(None) {
+        Ok(r) => Ok(Some(r)),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Current tip of `name`, which is either the trunk or a local branch.
+fn branch_tip(repo: &Repository, trunk: &Trunk, name: &str) -> Result<Oid, git2::Error> {
+    if name == trunk.name {
+        return Ok(trunk.oid);
+    }
+    Ok(repo
+        .find_branch(name, BranchType::Local)?
+        .get()
+        .peel_to_commit()?
+        .id())
+}
@@ -0,0 +1,381 @@
+use crate::report;
+use colored::Colorize;
+use git2::{
+    build::TreeUpdateBuilder, Branch, BranchType, DiffOptions, FileMode, Oid, Pathspec,
+    PathspecFlags, Repository, Tree,
+};
+use gx::checkout::has_uncommitted_changes;
+use gx::error::GxError;
+use gx::meta;
+use gx::restack;
+use gx::stack::{Stack, StackBranch};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// How `gx stack split` divides the current branch. Every assignment has the
+/// form `<what>=<branch>`.
+pub enum SplitBy<'a> {
+    /// `<commit>=<branch>`: `branch` ends at `commit` and takes every commit
+    /// after the previous assignment.
+    Commits(&'a [String]),
+    /// `<glob>=<branch>`: the changes to files matching `glob` move to
+    /// `branch`, as a single commit.
+    Files(&'a [String]),
+}
+
+/// The new branches with their tips, bottom first, and the tip left for the
+/// branch being split if anything remains on it.
+type Plan = (Vec<(String, Oid)>, Option<Oid>);
+
+/// Splits the current branch into a chain of branches stacked on its parent,
+/// in the order the new branches are first named.
+///
+/// Whatever no assignment covers stays on the current branch, which goes on
+/// top of the chain. If nothing is left, the current branch is replaced by the
+/// chain, and the branches stacked on it are moved onto the chain's top and
+/// restacked.
+pub fn split(repo: &Repository, trunk_name: Option<&str>, by: SplitBy) -> Result<(), GxError> {
+    restack::ensure_no_operation(repo)?;
+    let (mut stack, head_branch) = Stack::current(repo, trunk_name, "to split it")?;
+    let branch = stack.find(&head_branch).unwrap().clone();
+    let mut commits = stack.commits(repo, &branch)?;
+    commits.reverse();
+    if commits.is_empty() {
+        return Err(GxError::InvalidState(format!(
+            "Branch {head_branch} has no commits of its own to split."
+        )));
+    }
+
+    // Where the branch's own commits start. The recorded base can be further
+    // down, for instance if the branch was rebased onto a newer trunk outside
+    // gx, and then the changes in between are not the branch's to split.
+    let start = repo.find_commit(commits[0])?.parent_id(0)?;
+    let descendants = stack.descendants(&head_branch);
+
+    // The new branches bottom first, and where the current branch ends up.
+    let (chain, rest) = match by {
+        SplitBy::Commits(assignments) => split_commits(repo, &branch, &commits, assignments)?,
+        SplitBy::Files(assignments) => {
+            let mut rewritten: Vec<&str> = descendants.iter().map(String::as_str).collect();
+            rewritten.push(&head_branch);
+            if let Some((name, oid)) = restack::find_merge(repo, &stack, &rewritten)? {
+                return Err(GxError::UnsupportedHistory(format!(
+                    "Branch {} contains merge commit {}, which split cannot rewrite.",
+                    name.yellow().bold(),
+                    &oid.to_string()[0..7],
+                )));
+            }
+            if !descendants.is_empty() && has_uncommitted_changes(repo)? {
+                return Err(GxError::InvalidState(
+                    "You have uncommitted changes. Commit or stash them before splitting, so the branches above can be restacked.".to_string(),
+                ));
+            }
+            split_files(repo, &branch, start, &commits, assignments)?
+        }
+    };
+
+    let mut names: Vec<&str> = chain.iter().map(|(n, _)| n.as_str()).collect();
+    if rest.is_some() && names.contains(&head_branch.as_str()) {
+        return Err(GxError::InvalidState(format!(
+            "Some commits are left over and stay on {head_branch}. Give the new branch another name."
+        )));
+    }
+    if rest.is_some() {
+        names.push(&head_branch);
+    }
+    let top = names.last().unwrap().to_string();
+    let top_tip = rest.or(chain.last().map(|(_, tip)| *tip)).unwrap();
+
+    for (name, tip) in &chain {
+        repo.reference(&format!("refs/heads/{name}"), *tip, true, "gx split")?;
+    }
+    if let Some(tip) = rest {
+        repo.reference(&format!("refs/heads/{head_branch}"), tip, true, "gx split")?;
+    }
+    // The top of the chain has the same files as the branch had, so HEAD can
+    // move there without touching the working tree.
+    repo.set_head(&format!("refs/heads/{top}"))?;
+    if !names.contains(&head_branch.as_str()) {
+        repo.find_branch(&head_branch, BranchType::Local)?
+            .delete()?;
+        meta::remove(repo, &head_branch)?;
+    }
+
+    // Put the chain in the stack in place of the branch, and record the new
+    // shape so the branches above stay attached to it.
+    let mut replacement = Vec::new();
+    let (mut parent, mut base) = (branch.parent.clone(), start);
+    for (name, tip) in chain
+        .iter()
+        .chain(rest.map(|tip| (head_branch.clone(), tip)).iter())
+    {
+        replacement.push(StackBranch {
+            name: name.clone(),
+            tip: *tip,
+            parent: parent.clone(),
+            base,
+            tracked: true,
+        });
+        (parent, base) = (name.clone(), *tip);
+    }
+    let at = stack
+        .branches
+        .iter()
+        .position(|b| b.name == head_branch)
+        .unwrap();
+    stack.branches.splice(at..=at, replacement);
+    for b in stack.branches.iter_mut() {
+        if b.parent == head_branch && !names.contains(&b.name.as_str()) {
+            b.parent = top.clone();
+        }
+    }
+    restack::record_stack(repo, &stack)?;
+
+    let names: Vec<String> = names
+        .iter()
+        .map(|n| n.yellow().bold().to_string())
+        .collect();
+    println!(
+        "{} {} into {}.",
+        "Split".green().bold(),
+        head_branch.yellow().bold(),
+        names.join(", ")
+    );
+
+    if descendants.is_empty() || top_tip == branch.tip {
+        return Ok(());
+    }
+    let op = restack::start_operation(repo, "split", &stack, &top, descendants)?;
+    report::restack(restack::run_operation(repo, op)?)
+}
+
+/// Splits `branch` at the commits named by `assignments`, without rewriting
+/// anything. Commits above the last assignment stay on `branch`.
+fn split_commits(
+    repo: &Repository,
+    branch: &StackBranch,
+    commits: &[Oid],
+    assignments: &[String],
+) -> Result<Plan, GxError> {
+    let mut ends = Vec::new();
+    for assignment in assignments {
+        let (rev, name) = parse_assignment(assignment, "<commit>=<branch>")?;
+        let oid = repo.revparse_single(rev)?.peel_to_commit()?.id();
+        let position = commits.iter().position(|&c| c == oid).ok_or_else(|| {
+            GxError::InvalidState(format!(
+                "{rev} is not one of the commits of {}.",
+                branch.name
+            ))
+        })?;
+        ends.push((position, name.to_string()));
+    }
+    ends.sort();
+    for pair in ends.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            return Err(GxError::InvalidState(format!(
+                "Commit {} is assigned to both {} and {}.",
+                &commits[pair[0].0].to_string()[0..7],
+                pair[0].1,
+                pair[1].1
+            )));
+        }
+    }
+    check_names(repo, branch, ends.iter().map(|(_, n)| n.as_str()))?;
+
+    let rest = if ends.last().unwrap().0 + 1 < commits.len() {
+        Some(branch.tip)
+    } else {
+        None
+    };
+    let chain = ends
+        .into_iter()
+        .map(|(position, name)| (name, commits[position]))
+        .collect();
+    Ok((chain, rest))
+}
+
+/// Moves the changes `branch` makes since `start` to the files matched by
+/// `assignments` to one new commit per new branch, and replays the commits of
+/// `branch` on top without those changes, dropping commits left empty.
+fn split_files(
+    repo: &Repository,
+    branch: &StackBranch,
+    start: Oid,
+    commits: &[Oid],
+    assignments: &[String],
+) -> Result<Plan, GxError> {
+    let mut groups: Vec<(String, Vec<&str>)> = Vec::new();
+    for assignment in assignments {
+        let (glob, name) = parse_assignment(assignment, "<glob>=<branch>")?;
+        match groups.iter_mut().find(|(n, _)| n == name) {
+            Some((_, globs)) => globs.push(glob),
+            None => groups.push((name.to_string(), vec![glob])),
+        }
+    }
+    check_names(repo, branch, groups.iter().map(|(n, _)| n.as_str()))?;
+
+    let base_tree = repo.find_commit(start)?.tree()?;
+    let tip_tree = repo.find_commit(branch.tip)?.tree()?;
+    let mut changed = Vec::new();
+    for delta in repo
+        .diff_tree_to_tree(Some(&base_tree), Some(&tip_tree), None)?
+        .deltas()
+    {
+        for path in [delta.old_file().path(), delta.new_file().path()]
+            .into_iter()
+            .flatten()
+        {
+            if !changed.iter().any(|p: &PathBuf| p == path) {
+                changed.push(path.to_path_buf());
+            }
+        }
+    }
+
+    let mut moved: Vec<Vec<PathBuf>> = Vec::new();
+    for (name, globs) in &groups {
+        let pathspec = Pathspec::new(globs.iter())?;
+        let mut paths = Vec::new();
+        changed.retain(|path| {
+            let matches = pathspec.matches_path(path, PathspecFlags::DEFAULT);
+            if matches {
+                paths.push(path.clone());
+            }
+            !matches
+        });
+        if paths.is_empty() {
+            return Err(GxError::InvalidState(format!(
+                "No changes on {} match {} (for {name}).",
+                branch.name,
+                globs.join(", ")
+            )));
+        }
+        moved.push(paths);
+    }
+
+    let signature = repo.signature()?;
+    let mut parent = repo.find_commit(start)?;
+    let mut chain = Vec::new();
+    for ((name, _), paths) in groups.iter().zip(&moved) {
+        let tree = overlay(repo, &parent.tree()?, &tip_tree, paths)?;
+        // Credit the new commit to the oldest commit touching these files.
+        let mut first = None;
+        for &oid in commits {
+            let commit = repo.find_commit(oid)?;
+            let mut options = DiffOptions::new();
+            for path in paths {
+                options.pathspec(path);
+            }
+            let diff = repo.diff_tree_to_tree(
+                Some(&commit.parent(0)?.tree()?),
+                Some(&commit.tree()?),
+                Some(&mut options),
+            )?;
+            if diff.deltas().len() > 0 {
+                first = Some(commit);
+                break;
+            }
+        }
+        let source = first.ok_or_else(|| {
+            GxError::InvalidState(format!(
+                "None of the commits of {} change the files for {name}.",
+                branch.name
+            ))
+        })?;
+        let oid = repo.commit(
+            None,
+            &source.author(),
+            &signature,
+            source.message_raw().unwrap_or_default(),
+            &tree,
+            &[&parent],
+        )?;
+        parent = repo.find_commit(oid)?;
+        chain.push((name.clone(), oid));
+    }
+
+    let all_moved: Vec<PathBuf> = moved.into_iter().flatten().collect();
+    let mut rest = None;
+    for &oid in commits {
+        let commit = repo.find_commit(oid)?;
+        let tree = overlay(repo, &commit.tree()?, &tip_tree, &all_moved)?;
+        if tree.id() == parent.tree_id() {
+            continue;
+        }
+        let new_oid = repo.commit(
+            None,
+            &commit.author(),
+            &signature,
+            commit.message_raw().unwrap_or_default(),
+            &tree,
+            &[&parent],
+        )?;
+        parent = repo.find_commit(new_oid)?;
+        rest = Some(new_oid);
+    }
+    Ok((chain, rest))
+}
+
+/// `tree` with `paths` as they are in `from`, removed where `from` lacks them.
+fn overlay<'r>(
+    repo: &'r Repository,
+    tree: &Tree,
+    from: &Tree,
+    paths: &[PathBuf],
+) -> Result<Tree<'r>, GxError> {
+    let mut update = TreeUpdateBuilder::new();
+    for path in paths {
+        match from.get_path(path) {
+            Ok(entry) => update.upsert(path, entry.id(), file_mode(entry.filemode())),
+            Err(_) => update.remove(path),
+        };
+    }
+    Ok(repo.find_tree(update.create_updated(repo, tree)?)?)
+}
+
+fn file_mode(raw: i32) -> FileMode {
+    match raw {
+        0o100755 => FileMode::BlobExecutable,
+        0o120000 => FileMode::Link,
+        0o160000 => FileMode::Commit,
+        0o040000 => FileMode::Tree,
+        _ => FileMode::Blob,
+    }
+}
+
+/// Splits `<what>=<branch>` at the last `=`.
+fn parse_assignment<'a>(assignment: &'a str, form: &str) -> Result<(&'a str, &'a str), GxError> {
+    match assignment.rsplit_once('=') {
+        Some((what, name)) if !what.is_empty() && !name.is_empty() => Ok((what, name)),
+        _ => Err(GxError::InvalidState(format!(
+            "Expected {form}, got {assignment}."
+        ))),
+    }
+}
+
+/// Checks that the new branch names are valid, distinct and free. Only the
+/// branch being split may be reused.
+fn check_names<'a>(
+    repo: &Repository,
+    branch: &StackBranch,
+    names: impl Iterator<Item = &'a str>,
+) -> Result<(), GxError> {
+    let mut seen = HashSet::new();
+    for name in names {
+        if !Branch::name_is_valid(name)? {
+            return Err(GxError::InvalidState(format!(
+                "{name} is not a valid branch name."
+            )));
+        }
+        if !seen.insert(name) {
+            return Err(GxError::InvalidState(format!(
+                "{name} is assigned more than once."
+            )));
+        }
+        if name != branch.name && repo.find_branch(name, BranchType::Local).is_ok() {
+            return Err(GxError::InvalidState(format!(
+                "A branch named {name} already exists."
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,389 @@
+use crate::error::GxError;
+use crate::meta::{self, BranchMeta};
+use crate::trunk::{self, Trunk};
+use git2::{Branch, BranchType, ErrorCode, Oid, Repository, Sort};
+use std::collections::{HashMap, HashSet};
+
+/// A branch that is part of a stack.
+#[derive(Debug, Clone)]
+pub struct StackBranch {
+    pub name: String,
+    /// Commit the branch currently points at.
+    pub tip: Oid,
+    /// Branch this one is stacked on; the trunk's name for the bottom branch.
+    pub parent: String,
+    /// Commit the branch's own commits start after.
+    pub base: Oid,
+    /// Whether `parent` and `base` come from recorded metadata rather than
+    /// being inferred from history.
+    pub tracked: bool,
+}
+
+/// A tree of branches rooted at the trunk.
+pub struct Stack {
+    pub trunk: Trunk,
+    /// Branches ordered so that every parent comes before its children.
+    pub branches: Vec<StackBranch>,
+}
+
+impl Stack {
+    /// Builds the stack that `branch` belongs to: the chain of parents down to
+    /// the trunk, and every branch stacked above that chain's bottom branch.
+    ///
+    /// Returns `None` if `branch` is the trunk or not part of any stack.
+    pub fn for_branch(
+        repo: &Repository,
+        trunk: Trunk,
+        branch: &str,
+    ) -> Result<Option<Stack>, GxError> {
+        let branches = stack_branches(repo, &trunk)?;
+        let by_name: HashMap<&str, &StackBranch> =
+            branches.iter().map(|b| (b.name.as_str(), b)).collect();
+        if !by_name.contains_key(branch) {
+            return Ok(None);
+        }
+
+        let mut bottom = branch;
+        while let Some(parent) = by_name.get(by_name[bottom].parent.as_str()) {
+            bottom = &parent.name;
+        }
+
+        Ok(Some(Stack::rooted_at(trunk, &branches, bottom)))
+    }
+
+    /// Builds the stack of the checked out branch, for commands that work on
+    /// the current stack, and returns it with the branch's name.
+    ///
+    /// A detached HEAD, the trunk or a branch outside any stack is an error
+    /// whose hint ends with `action`, such as `to restack it`.
+    pub fn current(
+        repo: &Repository,
+        trunk_name: Option<&str>,
+        action: &str,
+    ) -> Result<(Stack, String), GxError> {
+        let head = repo.head()?;
+        if !head.is_branch() {
+            return Err(GxError::detached(&format!("a branch in a stack {action}")));
+        }
+        let head_branch = head.shorthand().unwrap_or_default().to_string();
+
+        let trunk = trunk::find_trunk(repo, trunk_name)?;
+        if head_branch == trunk.name {
+            return Err(GxError::InvalidState(format!(
+                "{head_branch} is the trunk branch. Switch to a branch in a stack {action}."
+            )));
+        }
+        match Stack::for_branch(repo, trunk, &head_branch)? {
+            Some(stack) => Ok((stack, head_branch)),
+            None => Err(GxError::not_in_stack(&head_branch)),
+        }
+    }
+
+    /// Builds every stack in the repository, one per branch stacked directly
+    /// on the trunk, in name order of those bottom branches.
+    pub fn all(repo: &Repository, trunk: Trunk) -> Result<Vec<Stack>, GxError> {
+        let branches = stack_branches(repo, &trunk)?;
+        Ok(branches
+            .iter()
+            .filter(|b| b.parent == trunk.name)
+            .map(|b| Stack::rooted_at(trunk.clone(), &branches, &b.name))
+            .collect())
+    }
+
+    /// The stack made of `bottom` and everything stacked above it. `branches`
+    /// must be ordered parents-first.
+    fn rooted_at(trunk: Trunk, branches: &[StackBranch], bottom: &str) -> Stack {
+        let mut members = HashSet::from([bottom.to_string()]);
+        let mut stack_branches = Vec::new();
+        for b in branches {
+            if b.name == bottom || members.contains(&b.parent) {
+                members.insert(b.name.clone());
+                stack_branches.push(b.clone());
+            }
+        }
+        Stack {
+            trunk,
+            branches: stack_branches,
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&StackBranch> {
+        self.branches.iter().find(|b| b.name == name)
+    }
+
+    /// Branches with nothing stacked on top of them, in name order.
+    pub fn tips(&self) -> Vec<&StackBranch> {
+        let mut tips: Vec<&StackBranch> = self
+            .branches
+            .iter()
+            .filter(|b| self.children(&b.name).is_empty())
+            .collect();
+        tips.sort_by(|a, b| a.name.cmp(&b.name));
+        tips
+    }
+
+    /// Branches stacked directly on top of `name`, in name order.
+    pub fn children(&self, name: &str) -> Vec<&StackBranch> {
+        self.branches.iter().filter(|b| b.parent == name).collect()
+    }
+
+    /// Names of the branches stacked above `name`, directly or not, parents
+    /// first.
+    pub fn descendants(&self, name: &str) -> Vec<String> {
+        let mut above = HashSet::from([name.to_string()]);
+        let mut descendants = Vec::new();
+        for b in &self.branches {
+            if above.contains(&b.parent) {
+                above.insert(b.name.clone());
+                descendants.push(b.name.clone());
+            }
+        }
+        descendants
+    }
+
+    /// Commits that belong to `branch` itself, newest first.
+    pub fn commits(
+        &self,
+        repo: &Repository,
+        branch: &StackBranch,
+    ) -> Result<Vec<Oid>, git2::Error> {
+        own_commits(repo, &self.trunk, branch)
+    }
+}
+
+/// Every stack in which `oid` is one of a branch's own commits, together with
+/// the name of that branch. Used to place a detached HEAD.
+pub fn stacks_containing(
+    repo: &Repository,
+    trunk: &Trunk,
+    oid: Oid,
+) -> Result<Vec<(Stack, String)>, GxError> {
+    let mut found: Vec<(Stack, String)> = Vec::new();
+    for b in stack_branches(repo, trunk)? {
+        if found.iter().any(|(s, _)| s.find(&b.name).is_some()) {
+            continue;
+        }
+        if !own_commits(repo, trunk, &b)?.contains(&oid) {
+            continue;
+        }
+        if let Some(stack) = Stack::for_branch(repo, trunk.clone(), &b.name)? {
+            found.push((stack, b.name));
+        }
+    }
+    Ok(found)
+}
+
+fn own_commits(
+    repo: &Repository,
+    trunk: &Trunk,
+    branch: &StackBranch,
+) -> Result<Vec<Oid>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL)?;
+    revwalk.simplify_first_parent()?;
+    revwalk.push(branch.tip)?;
+    revwalk.hide(branch.base)?;
+    revwalk.hide(trunk.oid)?;
+    revwalk.collect()
+}
+
+/// Every local branch, keyed by the commit it points at. Branches sharing a
+/// commit are listed in name order.
+pub fn get_local_branches(repo: &Repository) -> Result<HashMap<Oid, Vec<Branch<'_>>>, GxError> {
+    let mut branches: HashMap<Oid, Vec<Branch>> = HashMap::new();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        // Symbolic branches have no target of their own and are skipped.
+        if let Some(oid) = branch.get().target() {
+            branches.entry(oid).or_default().push(branch);
+        }
+    }
+    for at_commit in branches.values_mut() {
+        at_commit.sort_by_key(|b| b.name().ok().flatten().map(str::to_string));
+    }
+    Ok(branches)
+}
+
+/// Works out the parent of every local branch that is part of a stack, and
+/// returns them ordered parents-first.
+///
+/// Recorded metadata is used whenever its parent still exists. Other branches
+/// are attached to the nearest branch tip found on their first-parent history,
+/// or to the trunk.
+fn stack_branches(repo: &Repository, trunk: &Trunk) -> Result<Vec<StackBranch>, GxError> {
+    let local_branches = get_local_branches(repo)?;
+    let mut tips = HashMap::new();
+    for (oid, at_commit) in &local_branches {
+        for branch in at_commit {
+            if let Some(name) = branch.name()? {
+                if name != trunk.name {
+                    tips.insert(name.to_string(), *oid);
+                }
+            }
+        }
+    }
+
+    let mut recorded = HashMap::new();
+    for name in tips.keys() {
+        if let Some(m) = meta::read(repo, name)? {
+            recorded.insert(name.clone(), m);
+        }
+    }
+    let recorded_parents: HashSet<&str> = recorded.values().map(|m| m.parent.as_str()).collect();
+
+    let mut branches = HashMap::new();
+    for (name, &tip) in &tips {
+        let fork_point = match repo.merge_base(tip, trunk.oid) {
+            Ok(oid) => oid,
+            Err(e) if e.code() == ErrorCode::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if tip == fork_point
+            && !recorded.contains_key(name)
+            && !recorded_parents.contains(name.as_str())
+        {
+            // Already part of the trunk and nothing is stacked on it.
+            continue;
+        }
+        branches.insert(
+            name.clone(),
+            StackBranch {
+                name: name.clone(),
+                tip,
+                parent: trunk.name.clone(),
+                base: fork_point,
+                tracked: false,
+            },
+        );
+    }
+
+    let mut names: Vec<String> = branches.keys().cloned().collect();
+    names.sort();
+    let mut inferred = Vec::new();
+    for name in names {
+        if let Some(m) = recorded.get(&name) {
+            if m.parent == trunk.name || branches.contains_key(&m.parent) {
+                let b = branches.get_mut(&name).unwrap();
+                b.parent = m.parent.clone();
+                b.base = m.base;
+                b.tracked = true;
+                continue;
+            }
+        }
+        if let Some(found) = infer_parent(
+            repo,
+            trunk,
+            &branches[&name],
+            &local_branches,
+            &branches,
+            &recorded,
+        )? {
+            inferred.push((name, found));
+        }
+    }
+    for (name, (parent, base)) in inferred {
+        let b = branches.get_mut(&name).unwrap();
+        b.parent = parent;
+        b.base = base;
+    }
+
+    break_cycles(trunk, &mut branches);
+    Ok(order_parents_first(trunk, branches))
+}
+
+/// Finds the closest commit on `branch`'s first-parent history, not already
+/// part of the trunk, that another stack branch points at, and returns that
+/// branch and commit.
+///
+/// When several branches point at the commit, recorded metadata tells which
+/// one is stacked on top of the others, and `branch` goes on top of that one.
+/// Branches pointing at `branch`'s own tip are only candidates when they are
+/// tracked, since nothing else says which of the two comes first.
+fn infer_parent(
+    repo: &Repository,
+    trunk: &Trunk,
+    branch: &StackBranch,
+    local_branches: &HashMap<Oid, Vec<Branch>>,
+    branches: &HashMap<String, StackBranch>,
+    recorded: &HashMap<String, BranchMeta>,
+) -> Result<Option<(String, Oid)>, GxError> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL)?;
+    revwalk.simplify_first_parent()?;
+    revwalk.push(branch.tip)?;
+    revwalk.hide(trunk.oid)?;
+    for oid in revwalk {
+        let oid = oid?;
+        let Some(at_commit) = local_branches.get(&oid) else {
+            continue;
+        };
+        let mut candidates = Vec::new();
+        for other in at_commit {
+            let Some(name) = other.name()? else {
+                continue;
+            };
+            if name == branch.name || !branches.contains_key(name) {
+                continue;
+            }
+            if oid == branch.tip && recorded.get(name).is_none_or(|m| m.parent == branch.name) {
+                continue;
+            }
+            candidates.push(name);
+        }
+
+        let top = candidates.iter().find(|c| {
+            !candidates
+                .iter()
+                .any(|o| recorded.get(*o).is_some_and(|m| m.parent == **c))
+        });
+        if let Some(top) = top.or(candidates.first()) {
+            return Ok(Some((top.to_string(), oid)));
+        }
+    }
+    Ok(None)
+}
+
+/// Reattaches to the trunk any branch whose recorded parents form a loop.
+fn break_cycles(trunk: &Trunk, branches: &mut HashMap<String, StackBranch>) {
+    let names: Vec<String> = branches.keys().cloned().collect();
+    for name in names {
+        let mut seen = HashSet::new();
+        let mut curr = name.clone();
+        while let Some(b) = branches.get(&curr) {
+            if !seen.insert(curr.clone()) {
+                let b = branches.get_mut(&curr).unwrap();
+                b.parent = trunk.name.clone();
+                break;
+            }
+            curr = b.parent.clone();
+        }
+    }
+}
+
+fn order_parents_first(
+    trunk: &Trunk,
+    mut branches: HashMap<String, StackBranch>,
+) -> Vec<StackBranch> {
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    for b in branches.values() {
+        children
+            .entry(b.parent.clone())
+            .or_default()
+            .push(b.name.clone());
+    }
+    for c in children.values_mut() {
+        c.sort();
+    }
+
+    let mut ordered = Vec::new();
+    let mut pending = vec![trunk.name.clone()];
+    while let Some(name) = pending.pop() {
+        if let Some(c) = children.get(&name) {
+            pending.extend(c.iter().rev().cloned());
+        }
+        if let Some(b) = branches.remove(&name) {
+            ordered.push(b);
+        }
+    }
+    ordered
+}
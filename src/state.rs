@@ -0,0 +1,120 @@
+use crate::error::GxError;
+use crate::meta::BranchMeta;
+use git2::{Oid, Repository};
+use std::{fs, path::PathBuf};
+
+/// A branch as it was before a multi-branch operation touched it.
+#[derive(Debug, Clone)]
+pub struct OriginalBranch {
+    pub name: String,
+    pub tip: Oid,
+    pub meta: Option<BranchMeta>,
+}
+
+/// A multi-branch operation that stopped part way, persisted under `.git/gx/`
+/// so it can be continued or aborted by a later gx invocation.
+///
+/// The file is line based, one `key value...` entry per line:
+///
+/// ```text
+/// kind restack
+/// trunk main
+/// head feature-c
+/// original feature-b 4f1c... feature-a 9e07...
+/// original feature-c 0b3d... -
+/// pending feature-b
+/// pending feature-c
+/// ```
+#[derive(Debug, Clone)]
+pub struct Operation {
+    /// Command that started the operation, used in messages.
+    pub kind: String,
+    /// Trunk the operation was started against.
+    pub trunk: String,
+    /// Branch to check out once the operation finishes or is aborted.
+    pub head: String,
+    /// Every branch the operation may rewrite, as it was before starting.
+    pub original: Vec<OriginalBranch>,
+    /// Branches still to be restacked, parents first. The first one is the
+    /// branch being rebased when the operation stopped.
+    pub pending: Vec<String>,
+}
+
+fn state_path(repo: &Repository) -> PathBuf {
+    repo.path().join("gx").join("operation")
+}
+
+/// Reads the operation in progress, if any.
+pub fn read(repo: &Repository) -> Result<Option<Operation>, GxError> {
+    let path = state_path(repo);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path)?;
+
+    let mut op = Operation {
+        kind: String::new(),
+        trunk: String::new(),
+        head: String::new(),
+        original: Vec::new(),
+        pending: Vec::new(),
+    };
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        match fields.as_slice() {
+            ["kind", kind] => op.kind = kind.to_string(),
+            ["trunk", trunk] => op.trunk = trunk.to_string(),
+            ["head", head] => op.head = head.to_string(),
+            ["original", name, tip, "-"] => op.original.push(OriginalBranch {
+                name: name.to_string(),
+                tip: Oid::from_str(tip)?,
+                meta: None,
+            }),
+            ["original", name, tip, parent, base] => op.original.push(OriginalBranch {
+                name: name.to_string(),
+                tip: Oid::from_str(tip)?,
+                meta: Some(BranchMeta {
+                    parent: parent.to_string(),
+                    base: Oid::from_str(base)?,
+                }),
+            }),
+            ["pending", name] => op.pending.push(name.to_string()),
+            _ => {
+                return Err(format!("Corrupt operation state in {}: {line}", path.display()).into())
+            }
+        }
+    }
+    Ok(Some(op))
+}
+
+/// Persists `op`, replacing any previously saved operation.
+pub fn write(repo: &Repository, op: &Operation) -> Result<(), GxError> {
+    let mut contents = format!("kind {}\ntrunk {}\nhead {}\n", op.kind, op.trunk, op.head);
+    for b in &op.original {
+        match &b.meta {
+            Some(m) => {
+                contents += &format!("original {} {} {} {}\n", b.name, b.tip, m.parent, m.base)
+            }
+            None => contents += &format!("original {} {} -\n", b.name, b.tip),
+        }
+    }
+    for name in &op.pending {
+        contents += &format!("pending {name}\n");
+    }
+
+    let path = state_path(repo);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Deletes the saved operation. Does nothing if there is none.
+pub fn remove(repo: &Repository) -> Result<(), GxError> {
+    let path = state_path(repo);
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use crate::stack::{Stack, StackBranch};
+use git2::{BranchType, ErrorCode, Repository};
+
+/// How a stack branch compares to its upstream and to its parent.
+pub struct BranchStatus {
+    /// Short name of the remote-tracking branch, e.g. `origin/feature-a`.
+    pub upstream: Option<String>,
+    /// Commits on the branch that are not on its upstream.
+    pub ahead: Option<usize>,
+    /// Commits on the upstream that are not on the branch.
+    pub behind: Option<usize>,
+    /// The branch has an upstream configured but the remote branch is gone,
+    /// usually because it was deleted after its pull request merged.
+    pub upstream_gone: bool,
+    /// The branch has commits its upstream lacks, or was never pushed.
+    pub needs_push: bool,
+    /// The parent, or the trunk for the bottom branch, has moved since the
+    /// branch was last restacked onto it.
+    pub needs_restack: bool,
+}
+
+/// Works out the status of `branch`, which belongs to `stack`.
+pub fn branch_status(
+    repo: &Repository,
+    stack: &Stack,
+    branch: &StackBranch,
+) -> Result<BranchStatus, git2::Error> {
+    let needs_restack = if branch.parent == stack.trunk.name {
+        stack.trunk.oid != branch.base
+    } else {
+        match stack.find(&branch.parent) {
+            Some(parent) => parent.tip != branch.base,
+            None => false,
+        }
+    };
+
+    let local = repo.find_branch(&branch.name, BranchType::Local)?;
+    let upstream = match local.upstream() {
+        Ok(u) => Some(u),
+        Err(e) if e.code() == ErrorCode::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let upstream = match upstream {
+        Some(u) => u,
+        None => {
+            let configured = repo
+                .config()?
+                .get_string(&format!("branch.{}.merge", branch.name))
+                .is_ok();
+            return Ok(BranchStatus {
+                upstream: None,
+                ahead: None,
+                behind: None,
+                upstream_gone: configured,
+                needs_push: true,
+                needs_restack,
+            });
+        }
+    };
+
+    let upstream_oid = upstream.get().peel_to_commit()?.id();
+    let (ahead, behind) = repo.graph_ahead_behind(branch.tip, upstream_oid)?;
+    Ok(BranchStatus {
+        upstream: upstream.name()?.map(str::to_string),
+        ahead: Some(ahead),
+        behind: Some(behind),
+        upstream_gone: false,
+        needs_push: ahead > 0,
+        needs_restack,
+    })
+}
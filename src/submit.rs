@@ -0,0 +1,226 @@
+use crate::report;
+use colored::Colorize;
+use git2::{BranchType, Repository};
+use gx::error::GxError;
+use gx::github::{GitHub, PullRequest};
+use gx::meta;
+use gx::push;
+use gx::remote;
+use gx::stack::Stack;
+
+/// Markers around the part of a pull request description that gx maintains.
+const SECTION_START: &str = "<!-- gx:stack:start -->";
+const SECTION_END: &str = "<!-- gx:stack:end -->";
+
+/// Pushes the current stack and creates or updates one pull request per
+/// branch, each based on the branch below it.
+pub fn submit(repo: &Repository, trunk_name: Option<&str>) -> Result<(), GxError> {
+    let (stack, _) = Stack::current(repo, trunk_name, "to submit it")?;
+
+    let remote_name = remote::trunk_remote(repo, &stack.trunk);
+    let github = GitHub::from_repo(repo, &remote_name)?;
+    if !report::push(&push::push_stack(repo, &stack)?) {
+        return Err(GxError::Other(
+            "Some branches could not be pushed. Fix them and submit again.".to_string(),
+        ));
+    }
+
+    let mut submitted = Vec::new();
+    for b in &stack.branches {
+        let commits = stack.commits(repo, b)?;
+        let Some(&first_commit) = commits.last() else {
+            println!(
+                "{:<10} {} (no commits)",
+                "skipped".dimmed(),
+                b.name.yellow().bold()
+            );
+            continue;
+        };
+
+        let head = remote_branch_name(repo, &b.name)?;
+        let base = if b.parent == stack.trunk.name {
+            trunk_branch_name(repo, &stack.trunk.name, &remote_name)?
+        } else {
+            remote_branch_name(repo, &b.parent)?
+        };
+
+        let recorded = match meta::read_pr(repo, &b.name)? {
+            Some(number) => Some(github.get_pr(number)?).filter(|pr| pr.state == "open"),
+            None => None,
+        };
+        let existing = match recorded {
+            Some(pr) => Some(pr),
+            None => github.find_open_pr(&head)?,
+        };
+
+        let (status, pr) = match existing {
+            Some(pr) if pr.base.name == base => ("unchanged".dimmed(), pr),
+            Some(pr) => (
+                "updated".green().bold(),
+                github.update_base(pr.number, &base)?,
+            ),
+            None => {
+                let commit = repo.find_commit(first_commit)?;
+                let title = commit.summary().unwrap_or(&b.name).to_string();
+                let body = commit.body().unwrap_or_default().to_string();
+                (
+                    "created".green().bold(),
+                    github.create_pr(&head, &base, &title, &body)?,
+                )
+            }
+        };
+        meta::write_pr(repo, &b.name, pr.number)?;
+        meta::write_pr_state(repo, &b.name, pr.status())?;
+        println!(
+            "{:<10} {} #{} ({} <- {}) {}",
+            status,
+            b.name.yellow().bold(),
+            pr.number,
+            base,
+            head,
+            pr.html_url.blue(),
+        );
+        submitted.push(pr);
+    }
+
+    let mut updated = 0;
+    for pr in &submitted {
+        let body = pr.body.as_deref().unwrap_or_default();
+        let new_body = replace_section(
+            body,
+            &stack_section(&stack.trunk.name, &submitted, pr.number),
+        );
+        if new_body != body {
+            github.update_body(pr.number, &new_body)?;
+            updated += 1;
+        }
+    }
+    if updated > 0 {
+        println!("Updated the stack section of {updated} pull request description(s).");
+    }
+    Ok(())
+}
+
+/// Markdown listing every pull request of the stack from the top down to the
+/// trunk, pointing out `current`.
+fn stack_section(trunk: &str, prs: &[PullRequest], current: u64) -> String {
+    let mut section = format!("{SECTION_START}\n**Stack**\n\n");
+    for pr in prs.iter().rev() {
+        if pr.number == current {
+            section += &format!("- #{} \u{1f448}\n", pr.number);
+        } else {
+            section += &format!("- #{}\n", pr.number);
+        }
+    }
+    section += &format!("- `{trunk}`\n{SECTION_END}");
+    section
+}
+
+/// Puts `section` in place of the existing gx section of `body`, or appends it
+/// if there is none. The rest of the description is left as it is.
+///
+/// The section ends at the first end marker after a start marker, and starts
+/// at the last start marker before that, so stray markers on either side of
+/// it, say from quoting or editing by hand, are left in the text.
+fn replace_section(body: &str, section: &str) -> String {
+    if let Some((start, end)) = find_section(body) {
+        return format!("{}{section}{}", &body[..start], &body[end..]);
+    }
+    if body.trim().is_empty() {
+        section.to_string()
+    } else {
+        format!("{}\n\n{section}", body.trim_end())
+    }
+}
+
+/// Byte range of the gx section in `body`, markers included.
+fn find_section(body: &str) -> Option<(usize, usize)> {
+    let first = body.find(SECTION_START)?;
+    let end = first + body[first..].find(SECTION_END)?;
+    let start = body[..end].rfind(SECTION_START)?;
+    Some((start, end + SECTION_END.len()))
+}
+
+/// Name of the branch on the remote that `name` is pushed to.
+fn remote_branch_name(repo: &Repository, name: &str) -> Result<String, GxError> {
+    let merge = repo
+        .config()?
+        .get_string(&format!("branch.{name}.merge"))
+        .unwrap_or_else(|_| format!("refs/heads/{name}"));
+    Ok(merge
+        .strip_prefix("refs/heads/")
+        .unwrap_or(&merge)
+        .to_string())
+}
+
+/// Name of the trunk branch on `remote_name`.
+fn trunk_branch_name(repo: &Repository, trunk: &str, remote_name: &str) -> Result<String, GxError> {
+    if repo.find_branch(trunk, BranchType::Local).is_ok() {
+        return remote_branch_name(repo, trunk);
+    }
+    Ok(trunk
+        .strip_prefix(&format!("{remote_name}/"))
+        .unwrap_or(trunk)
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gx::github::PullRequestRef;
+
+    fn pr(number: u64) -> PullRequest {
+        PullRequest {
+            number,
+            html_url: format!("https://github.com/o/r/pull/{number}"),
+            state: "open".to_string(),
+            draft: false,
+            merged_at: None,
+            body: None,
+            base: PullRequestRef {
+                name: "main".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn stack_section_lists_the_stack_from_the_top() {
+        let section = stack_section("main", &[pr(1), pr(2)], 1);
+        assert_eq!(
+            section,
+            format!("{SECTION_START}\n**Stack**\n\n- #2\n- #1 \u{1f448}\n- `main`\n{SECTION_END}")
+        );
+    }
+
+    #[test]
+    fn replace_section_appends_when_there_is_none() {
+        assert_eq!(replace_section("", "S"), "S");
+        assert_eq!(replace_section("Intro\n\n", "S"), "Intro\n\nS");
+    }
+
+    #[test]
+    fn replace_section_keeps_the_rest_of_the_body() {
+        let body = format!("Intro\n\n{SECTION_START}\nold\n{SECTION_END}\n\nOutro");
+        assert_eq!(replace_section(&body, "S"), "Intro\n\nS\n\nOutro");
+    }
+
+    #[test]
+    fn replace_section_ignores_a_start_marker_without_an_end() {
+        let body = format!("Intro {SECTION_START} notes");
+        let replaced = replace_section(&body, "S");
+        assert_eq!(replaced, format!("{body}\n\nS"));
+
+        let section = format!("{SECTION_START}\nnew\n{SECTION_END}");
+        let once = replace_section(&body, &section);
+        assert_eq!(replace_section(&once, &section), once);
+    }
+
+    #[test]
+    fn replace_section_ignores_a_start_marker_after_the_section() {
+        let body = format!("Intro\n\n{SECTION_START}\nold\n{SECTION_END}\n\n> {SECTION_START}");
+        assert_eq!(
+            replace_section(&body, "S"),
+            format!("Intro\n\nS\n\n> {SECTION_START}")
+        );
+    }
+}
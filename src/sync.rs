@@ -0,0 +1,243 @@
+use crate::checkout::has_uncommitted_changes;
+use crate::error::GxError;
+use crate::meta;
+use crate::remote;
+use crate::restack::{self, Report};
+use crate::stack::{Stack, StackBranch};
+use crate::trunk::Trunk;
+use git2::{BranchType, Commit, Oid, Repository, Sort};
+use std::collections::HashSet;
+
+/// What fetching did to a local trunk branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrunkUpdate {
+    /// The trunk is already at its upstream, has none, or is a remote branch.
+    Unchanged,
+    FastForwarded(Oid),
+    /// The trunk has commits its upstream lacks and was left alone.
+    Diverged,
+}
+
+/// A sync that has fetched the trunk and found the merged branches of the
+/// current stack, ready to be carried out with [`apply`].
+pub struct Plan {
+    /// Remote the trunk was fetched from.
+    pub remote: String,
+    pub trunk_update: TrunkUpdate,
+    /// The current stack, on top of the updated trunk.
+    pub stack: Stack,
+    /// Branches of `stack` whose changes have all landed in the trunk, parents
+    /// first.
+    pub merged: Vec<String>,
+    head: String,
+}
+
+/// What [`apply`] did.
+pub struct Synced {
+    /// Merged branches that were deleted, parents first.
+    pub deleted: Vec<String>,
+    pub restack: Report,
+}
+
+/// Fetches the trunk, fast-forwards a local trunk branch to it and finds the
+/// branches of the current stack that have landed in it.
+pub fn plan(repo: &Repository, trunk_name: Option<&str>) -> Result<Plan, GxError> {
+    restack::ensure_no_operation(repo)?;
+    let (stack, _) = Stack::current(repo, trunk_name, "to sync it")?;
+    if has_uncommitted_changes(repo)? {
+        return Err(GxError::InvalidState(
+            "You have uncommitted changes. Commit or stash them before syncing.".to_string(),
+        ));
+    }
+    let remote_name = remote::trunk_remote(repo, &stack.trunk);
+    remote::fetch(repo, &remote_name)?;
+    let trunk_update = fast_forward_trunk(repo, &stack.trunk)?;
+
+    // Build the stack again on top of the updated trunk.
+    let (stack, head_branch) = Stack::current(repo, Some(&stack.trunk.name), "to sync it")?;
+    let merged = merged_branches(repo, &stack)?;
+    Ok(Plan {
+        remote: remote_name,
+        trunk_update,
+        stack,
+        merged,
+        head: head_branch,
+    })
+}
+
+/// Rebases the branches of the planned stack that were not merged onto the
+/// new trunk tip, deleting the merged ones first if `delete` is set.
+///
+/// Merged branches are never restacked, which would hide that they were
+/// merged. Whether or not they are deleted, their children move onto the
+/// closest branch below that was not merged. Branches containing merge
+/// commits are only rebased with `linearize`, see [`restack::restack`].
+pub fn apply(
+    repo: &Repository,
+    plan: Plan,
+    delete: bool,
+    linearize: bool,
+) -> Result<Synced, GxError> {
+    let Plan {
+        stack,
+        merged,
+        head,
+        ..
+    } = plan;
+    let merged: HashSet<&str> = merged.iter().map(String::as_str).collect();
+    let surviving: Vec<String> = stack
+        .branches
+        .iter()
+        .filter(|b| !merged.contains(b.name.as_str()))
+        .map(|b| b.name.clone())
+        .collect();
+    if !linearize {
+        let names: Vec<&str> = surviving.iter().map(String::as_str).collect();
+        if let Some((name, oid)) = restack::find_merge(repo, &stack, &names)? {
+            return Err(restack::merge_refusal("sync", &name, oid));
+        }
+    }
+    let mut op = restack::start_operation(repo, "sync", &stack, &head, surviving.clone())?;
+
+    for b in &stack.branches {
+        if merged.contains(b.name.as_str()) || !merged.contains(b.parent.as_str()) {
+            continue;
+        }
+        let mut parent = b.parent.as_str();
+        while merged.contains(parent) {
+            parent = &stack.find(parent).unwrap().parent;
+        }
+        if let Some(mut m) = meta::read(repo, &b.name)? {
+            m.parent = parent.to_string();
+            meta::write(repo, &b.name, &m)?;
+        }
+    }
+
+    let mut deleted = Vec::new();
+    if delete && !merged.is_empty() {
+        if merged.contains(head.as_str()) {
+            op.head = match repo.find_branch(&stack.trunk.name, BranchType::Local) {
+                Ok(_) => stack.trunk.name.clone(),
+                Err(_) => surviving.first().cloned().unwrap_or_default(),
+            };
+            // A checked out branch cannot be deleted.
+            repo.set_head_detached(repo.head()?.peel_to_commit()?.id())?;
+        }
+        for b in &stack.branches {
+            if merged.contains(b.name.as_str()) {
+                repo.find_branch(&b.name, BranchType::Local)?.delete()?;
+                meta::remove(repo, &b.name)?;
+                deleted.push(b.name.clone());
+            }
+        }
+    }
+
+    Ok(Synced {
+        deleted,
+        restack: restack::run_operation(repo, op)?,
+    })
+}
+
+/// Fast-forwards a local trunk branch to its upstream, if it has one.
+fn fast_forward_trunk(repo: &Repository, trunk: &Trunk) -> Result<TrunkUpdate, GxError> {
+    let local = match repo.find_branch(&trunk.name, BranchType::Local) {
+        Ok(b) => b,
+        Err(_) => return Ok(TrunkUpdate::Unchanged),
+    };
+    let upstream = match local.upstream() {
+        Ok(u) => u.get().peel_to_commit()?.id(),
+        Err(_) => return Ok(TrunkUpdate::Unchanged),
+    };
+    if upstream == trunk.oid {
+        return Ok(TrunkUpdate::Unchanged);
+    }
+    if !repo.graph_descendant_of(upstream, trunk.oid)? {
+        return Ok(TrunkUpdate::Diverged);
+    }
+
+    let ref_name = format!("refs/heads/{}", trunk.name);
+    repo.reference(&ref_name, upstream, true, "gx: sync fast-forward")?;
+    Ok(TrunkUpdate::FastForwarded(upstream))
+}
+
+/// Names of the stack branches whose changes are all part of the trunk,
+/// parents first.
+///
+/// Commits are compared by patch-id, so branches that were rebased or squashed
+/// when they were merged are detected too.
+fn merged_branches(repo: &Repository, stack: &Stack) -> Result<Vec<String>, GxError> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL)?;
+    revwalk.push(stack.trunk.oid)?;
+    for b in &stack.branches {
+        revwalk.hide(b.tip)?;
+    }
+    let mut trunk_patches = HashSet::new();
+    for oid in revwalk {
+        if let Some(id) = commit_patch_id(repo, &repo.find_commit(oid?)?)? {
+            trunk_patches.insert(id);
+        }
+    }
+
+    let mut merged = Vec::new();
+    for b in &stack.branches {
+        if is_merged(repo, stack, b, &trunk_patches)? {
+            merged.push(b.name.clone());
+        }
+    }
+    Ok(merged)
+}
+
+fn is_merged(
+    repo: &Repository,
+    stack: &Stack,
+    branch: &StackBranch,
+    trunk_patches: &HashSet<Oid>,
+) -> Result<bool, GxError> {
+    if branch.tip == branch.base {
+        // Nothing to merge yet.
+        return Ok(false);
+    }
+    if repo.graph_descendant_of(stack.trunk.oid, branch.tip)? {
+        return Ok(true);
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(branch.tip)?;
+    revwalk.hide(branch.base)?;
+    let mut all_applied = true;
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        match commit_patch_id(repo, &commit)? {
+            Some(id) if trunk_patches.contains(&id) => {}
+            Some(_) => all_applied = false,
+            None => {}
+        }
+    }
+    if all_applied {
+        return Ok(true);
+    }
+
+    // A squash merge turns the whole branch into a single commit.
+    let base_tree = repo.find_commit(branch.base)?.tree()?;
+    let tip_tree = repo.find_commit(branch.tip)?.tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&tip_tree), None)?;
+    Ok(trunk_patches.contains(&diff.patchid(None)?))
+}
+
+/// Patch-id of the changes `commit` makes on top of its first parent. Merge
+/// commits and commits without changes have none.
+fn commit_patch_id(repo: &Repository, commit: &Commit) -> Result<Option<Oid>, git2::Error> {
+    if commit.parent_count() > 1 {
+        return Ok(None);
+    }
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+    if diff.deltas().len() == 0 {
+        return Ok(None);
+    }
+    Ok(Some(diff.patchid(None)?))
+}
@@ -0,0 +1,88 @@
+use crate::error::GxError;
+use git2::{BranchType, ErrorCode, Oid, Repository};
+
+/// Git config key that overrides trunk auto-detection.
+const TRUNK_CONFIG_KEY: &str = "gx.trunk";
+
+/// Branch names tried, in order, when `origin/HEAD` is not set.
+const DEFAULT_TRUNK_NAMES: [&str; 2] = ["main", "master"];
+
+/// The branch every stack is built on top of.
+#[derive(Debug, Clone)]
+pub struct Trunk {
+    /// Branch name as the user would type it, e.g. `main` or `origin/main`.
+    pub name: String,
+    /// Commit the trunk branch currently points at.
+    pub oid: Oid,
+}
+
+/// Resolves the trunk branch.
+///
+/// An explicit `name` wins, followed by the `gx.trunk` config value. Otherwise
+/// the trunk is auto-detected from `origin/HEAD`, falling back to `main` and
+/// then `master`. A local branch is preferred over its remote-tracking branch.
+pub fn find_trunk(repo: &Repository, name: Option<&str>) -> Result<Trunk, GxError> {
+    if let Some(name) = name {
+        return resolve_branch(repo, name)?
+            .ok_or_else(|| format!("Trunk branch {name} does not exist.").into());
+    }
+
+    if let Ok(name) = repo.config()?.get_string(TRUNK_CONFIG_KEY) {
+        return resolve_branch(repo, &name)?.ok_or_else(|| {
+            format!("Trunk branch {name} (set via {TRUNK_CONFIG_KEY}) does not exist.").into()
+        });
+    }
+
+    if let Some(name) = origin_head_branch(repo)? {
+        if let Some(trunk) = resolve_branch(repo, &name)? {
+            return Ok(trunk);
+        }
+    }
+
+    for name in DEFAULT_TRUNK_NAMES {
+        if let Some(trunk) = resolve_branch(repo, name)? {
+            return Ok(trunk);
+        }
+    }
+
+    Err(format!(
+        "Could not detect the trunk branch. Pass --trunk or set it with `git config {TRUNK_CONFIG_KEY} <branch>`."
+    )
+    .into())
+}
+
+/// Returns the branch `refs/remotes/origin/HEAD` points to, without the remote prefix.
+fn origin_head_branch(repo: &Repository) -> Result<Option<String>, git2::Error> {
+    let origin_head = match repo.find_reference("refs/remotes/origin/HEAD") {
+        Ok(r) => r,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(origin_head
+        .symbolic_target()
+        .and_then(|target| target.strip_prefix("refs/remotes/origin/"))
+        .map(|name| name.to_string()))
+}
+
+/// Looks `name` up as a local branch, then as a remote-tracking branch, then as a
+/// branch on `origin`.
+fn resolve_branch(repo: &Repository, name: &str) -> Result<Option<Trunk>, git2::Error> {
+    let candidates = [
+        (name.to_string(), BranchType::Local),
+        (name.to_string(), BranchType::Remote),
+        (format!("origin/{name}"), BranchType::Remote),
+    ];
+    for (candidate, branch_type) in candidates {
+        let branch = match repo.find_branch(&candidate, branch_type) {
+            Ok(b) => b,
+            Err(e) if e.code() == ErrorCode::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let oid = branch.get().peel_to_commit()?.id();
+        return Ok(Some(Trunk {
+            name: candidate,
+            oid,
+        }));
+    }
+    Ok(None)
+}
@@ -0,0 +1,1030 @@
+mod common;
+
+use common::{short, MockGitHub, TestRepo};
+use git2::{BranchType, Oid};
+
+/// Runs `gx stack create` for `name` on top of the checked out branch and
+/// commits `commits` to it.
+fn create(t: &mut TestRepo, name: &str, commits: &[&str]) {
+    t.gx_ok(&["stack", "create", name]);
+    for message in commits {
+        t.commit(message);
+    }
+}
+
+/// The line `gx stack list --date iso` prints for commit `oid`.
+fn commit_line(t: &TestRepo, oid: Oid) -> String {
+    let commit = t.repo.find_commit(oid).unwrap();
+    let time = commit.author().when();
+    let date = chrono::DateTime::from_timestamp(time.seconds(), 0)
+        .unwrap()
+        .with_timezone(&chrono::FixedOffset::east_opt(time.offset_minutes() * 60).unwrap());
+    format!(
+        "{} - {} ({}) <A U Thor>",
+        short(oid),
+        commit.summary().unwrap(),
+        date.format("%Y-%m-%d %H:%M:%S %z")
+    )
+}
+
+/// Writes `contents` to `file` and stages it.
+fn stage(t: &TestRepo, file: &str, contents: &str) {
+    std::fs::write(t.path().join(file), contents).unwrap();
+    let mut index = t.repo.index().unwrap();
+    index.add_path(std::path::Path::new(file)).unwrap();
+    index.write().unwrap();
+}
+
+/// Contents of `file` at the tip of `branch`.
+fn file_at(t: &TestRepo, branch: &str, file: &str) -> String {
+    let tree = t.repo.find_commit(t.tip(branch)).unwrap().tree().unwrap();
+    let blob = t
+        .repo
+        .find_blob(tree.get_path(std::path::Path::new(file)).unwrap().id())
+        .unwrap();
+    String::from_utf8(blob.content().to_vec()).unwrap()
+}
+
+fn lines(out: &str) -> Vec<&str> {
+    out.lines().collect()
+}
+
+#[test]
+fn list_renders_a_linear_stack() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1", "a2"]);
+    create(&mut t, "b", &["b1"]);
+    let a = t.repo.find_commit(t.tip("a")).unwrap();
+
+    let out = t.gx_ok(&["stack", "list", "--date", "iso"]);
+    assert_eq!(
+        lines(&out),
+        [
+            "◉ b [needs push]".to_string(),
+            format!("│ {}", commit_line(&t, t.tip("b"))),
+            "◯ a [needs push]".to_string(),
+            format!("│ {}", commit_line(&t, a.id())),
+            format!("│ {}", commit_line(&t, a.parent_id(0).unwrap())),
+            "◯ main".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn list_renders_forks_as_a_tree() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+    t.checkout("a");
+    create(&mut t, "c", &["c1"]);
+
+    let out = t.gx_ok(&["stack", "list", "--date", "iso"]);
+    assert_eq!(
+        lines(&out),
+        [
+            "  ◉ c [needs push]".to_string(),
+            format!("  │ {}", commit_line(&t, t.tip("c"))),
+            "◯ │ b [needs push]".to_string(),
+            format!("│ │ {}", commit_line(&t, t.tip("b"))),
+            "├─╯".to_string(),
+            "◯ a [needs push]".to_string(),
+            format!("│ {}", commit_line(&t, t.tip("a"))),
+            "◯ main".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn list_shows_branches_sharing_a_commit() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &[]);
+    t.branch("backup");
+    t.checkout("b");
+
+    let out = t.gx_ok(&["stack", "list", "--date", "iso"]);
+    assert_eq!(
+        lines(&out),
+        [
+            "◯ backup [needs push]".to_string(),
+            "◉ b [needs push]".to_string(),
+            "◯ a [needs push]".to_string(),
+            format!("│ {} (b, backup)", commit_line(&t, t.tip("a"))),
+            "◯ main".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn list_marks_merge_commits() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    t.checkout("main");
+    t.commit("m1");
+    t.checkout("a");
+    let merge = t.merge("main");
+
+    let out = t.gx_ok(&["stack", "list"]);
+    assert!(
+        out.contains(&format!("{} [merge] - Merge branch 'main'", short(merge))),
+        "{out}"
+    );
+}
+
+#[test]
+fn list_marks_a_detached_head() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    let a1 = t.tip("a");
+    t.commit("a2");
+    t.detach(a1);
+
+    let out = t.gx_ok(&["stack", "list", "--date", "iso"]);
+    let lines = lines(&out);
+    assert!(lines[0].contains("HEAD is detached at"), "{out}");
+    assert!(lines[0].contains("`git switch a`"), "{out}");
+    assert!(lines.contains(&format!("◉ {} <- HEAD", commit_line(&t, a1)).as_str()));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&t.gx_ok(&["stack", "list", "--json"])).unwrap();
+    assert_eq!(json["current"], "a");
+    assert_eq!(json["detached_head"], a1.to_string());
+}
+
+#[test]
+fn list_json_describes_the_stack() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1", "b2"]);
+
+    let out = t.gx_ok(&["stack", "list", "--json"]);
+    let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["trunk"]["name"], "main");
+    assert_eq!(json["current"], "b");
+    assert_eq!(json["operation"], serde_json::Value::Null);
+
+    let branches = json["branches"].as_array().unwrap();
+    assert_eq!(branches.len(), 2);
+    assert_eq!(branches[1]["name"], "b");
+    assert_eq!(branches[1]["parent"], "a");
+    assert_eq!(branches[1]["base"], t.tip("a").to_string());
+    assert_eq!(branches[1]["upstream"], serde_json::Value::Null);
+    assert_eq!(branches[1]["needs_push"], true);
+    let commits = branches[1]["commits"].as_array().unwrap();
+    assert_eq!(commits.len(), 2);
+    assert_eq!(commits[0]["summary"], "b2");
+    assert_eq!(commits[0]["author"]["email"], "author@example.com");
+}
+
+#[test]
+fn list_stops_quietly_when_stdout_is_closed() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    for args in [
+        &["stack", "list"][..],
+        &["stack", "list", "--json"],
+        &["stack", "list", "--all"],
+    ] {
+        let (code, stderr) = t.gx_into_closed_pipe(args);
+        assert_eq!(code, 0, "gx {args:?}: {stderr}");
+        assert_eq!(stderr, "", "gx {args:?}");
+    }
+}
+
+#[test]
+fn list_json_on_the_trunk_is_an_empty_document() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    t.checkout("main");
+
+    let json: serde_json::Value =
+        serde_json::from_str(&t.gx_ok(&["stack", "list", "--json"])).unwrap();
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["current"], "main");
+    assert_eq!(json["detached_head"], serde_json::Value::Null);
+    assert_eq!(json["branches"], serde_json::json!([]));
+
+    t.detach(t.tip("main"));
+    let json: serde_json::Value =
+        serde_json::from_str(&t.gx_ok(&["stack", "list", "--json"])).unwrap();
+    assert_eq!(json["detached_head"], t.tip("main").to_string());
+    assert_eq!(json["branches"], serde_json::json!([]));
+}
+
+#[test]
+fn stacks_lists_every_stack() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+    t.checkout("main");
+    create(&mut t, "x", &["x1"]);
+
+    let out = t.gx_ok(&["stacks"]);
+    let headers: Vec<&str> = out.lines().filter(|l| l.contains("last commit")).collect();
+    assert_eq!(headers.len(), 2, "{out}");
+    assert!(
+        headers[0].starts_with("  b (2 branches, last commit"),
+        "{out}"
+    );
+    assert!(
+        headers[1].starts_with("* x (1 branch, last commit"),
+        "{out}"
+    );
+    assert!(headers[1].ends_with(", no PRs)"), "{out}");
+    assert_eq!(out, t.gx_ok(&["stack", "list", "--all"]));
+}
+
+#[test]
+fn restack_moves_children_onto_their_parent() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+    create(&mut t, "c", &["c1"]);
+    t.checkout("a");
+    t.commit("a2");
+    t.checkout("c");
+
+    let out = t.gx_ok(&["stack", "restack"]);
+    assert!(out.contains("Restacked b"), "{out}");
+    assert!(out.contains("Restacked c"), "{out}");
+    let b = t.repo.find_commit(t.tip("b")).unwrap();
+    assert_eq!(b.parent_id(0).unwrap(), t.tip("a"));
+    let c = t.repo.find_commit(t.tip("c")).unwrap();
+    assert_eq!(c.parent_id(0).unwrap(), t.tip("b"));
+    assert_eq!(t.head_branch(), "c");
+}
+
+#[test]
+fn restack_stops_on_conflicts_and_abort_restores() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &[]);
+    t.commit_file("a1", "file", "a");
+    create(&mut t, "b", &[]);
+    t.commit_file("b1", "file", "b");
+    let b_before = t.tip("b");
+    t.checkout("a");
+    t.commit_file("a2", "file", "conflict");
+    t.checkout("b");
+
+    let out = t.gx(&["stack", "restack"]);
+    assert_eq!(out.code, 6);
+    assert!(
+        out.stderr.contains("Restacking b hit conflicts."),
+        "{}",
+        out.stderr
+    );
+    assert!(t
+        .gx_ok(&["stack", "list"])
+        .starts_with("Note: A restack stopped"));
+
+    t.gx_ok(&["stack", "abort"]);
+    assert_eq!(t.tip("b"), b_before);
+    assert_eq!(t.head_branch(), "b");
+}
+
+#[test]
+fn skip_drops_the_conflicting_commit_and_carries_on() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &[]);
+    t.commit_file("a1", "file", "a");
+    create(&mut t, "b", &[]);
+    t.commit_file("b1", "file", "b");
+    t.commit("b2");
+    create(&mut t, "c", &["c1"]);
+    t.checkout("a");
+    t.commit_file("a2", "file", "conflict");
+    t.checkout("c");
+
+    let out = t.gx(&["stack", "restack"]);
+    assert_eq!(out.code, 6, "{}", out.stderr);
+
+    let out = t.gx_ok(&["stack", "skip"]);
+    assert!(out.contains("Restacked b"), "{out}");
+    assert!(out.contains("Restacked c"), "{out}");
+    let b = t.repo.find_commit(t.tip("b")).unwrap();
+    assert_eq!(b.summary(), Some("b2"));
+    assert_eq!(b.parent_id(0).unwrap(), t.tip("a"));
+    let c = t.repo.find_commit(t.tip("c")).unwrap();
+    assert_eq!(c.parent_id(0).unwrap(), b.id());
+    assert_eq!(t.head_branch(), "c");
+    assert_eq!(t.gx(&["stack", "continue"]).code, 1);
+}
+
+#[test]
+fn restack_errors_can_be_aborted() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &[]);
+    t.commit_file("b1", "new", "tracked");
+    let b_before = t.tip("b");
+    t.checkout("a");
+    t.commit("a2");
+    // An untracked file in the way of the commit being replayed.
+    std::fs::write(t.path().join("new"), "untracked\n").unwrap();
+
+    let out = t.gx(&["stack", "restack"]);
+    assert_eq!(out.code, 7, "{}", out.stderr);
+
+    assert!(t
+        .gx_ok(&["stack", "list"])
+        .starts_with("Note: A restack stopped"));
+    t.gx_ok(&["stack", "abort"]);
+    assert_eq!(t.tip("b"), b_before);
+    assert_eq!(t.head_branch(), "a");
+    assert_eq!(
+        config(&t, "branch.b.gx-base"),
+        t.repo
+            .find_commit(b_before)
+            .unwrap()
+            .parent_id(0)
+            .unwrap()
+            .to_string()
+    );
+}
+
+#[test]
+fn restack_errors_can_be_continued() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &[]);
+    t.commit_file("b1", "new", "tracked");
+    t.commit("b2");
+    t.checkout("a");
+    t.commit("a2");
+    std::fs::write(t.path().join("new"), "untracked\n").unwrap();
+    assert_eq!(t.gx(&["stack", "restack"]).code, 7);
+
+    std::fs::remove_file(t.path().join("new")).unwrap();
+    let out = t.gx_ok(&["stack", "continue"]);
+    assert!(out.contains("Restacked b"), "{out}");
+    let b2 = t.repo.find_commit(t.tip("b")).unwrap();
+    let b1 = b2.parent(0).unwrap();
+    assert_eq!(b1.summary(), Some("b1"));
+    assert_eq!(b1.parent_id(0).unwrap(), t.tip("a"));
+    assert_eq!(t.head_branch(), "a");
+}
+
+#[test]
+fn restack_refuses_merges_unless_linearized() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+    t.checkout("main");
+    t.commit("m1");
+    t.checkout("b");
+    t.merge("main");
+    t.checkout("a");
+    t.commit("a2");
+    t.checkout("b");
+
+    let out = t.gx(&["stack", "restack"]);
+    assert_eq!(out.code, 5, "{}", out.stderr);
+    assert!(out.stderr.contains("--linearize"), "{}", out.stderr);
+
+    t.gx_ok(&["stack", "restack", "--linearize"]);
+    let mut revwalk = t.repo.revwalk().unwrap();
+    revwalk.push(t.tip("b")).unwrap();
+    for oid in revwalk {
+        let commit = t.repo.find_commit(oid.unwrap()).unwrap();
+        assert!(commit.parent_count() <= 1);
+    }
+    assert!(t.repo.graph_descendant_of(t.tip("b"), t.tip("a")).unwrap());
+}
+
+#[test]
+fn navigation_moves_along_the_stack() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+    create(&mut t, "c", &["c1"]);
+
+    t.gx_ok(&["bottom"]);
+    assert_eq!(t.head_branch(), "a");
+    t.gx_ok(&["up", "2"]);
+    assert_eq!(t.head_branch(), "c");
+    t.gx_ok(&["down"]);
+    assert_eq!(t.head_branch(), "b");
+    t.gx_ok(&["top"]);
+    assert_eq!(t.head_branch(), "c");
+}
+
+#[test]
+fn commands_on_the_current_stack_refuse_the_trunk_and_a_detached_head_alike() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    let commands: [&[&str]; 11] = [
+        &["stack", "restack"],
+        &["stack", "sync"],
+        &["stack", "push"],
+        &["stack", "submit"],
+        &["stack", "modify"],
+        &["stack", "split", "--file", "*=b"],
+        &["stack", "fold"],
+        &["absorb"],
+        &["up"],
+        &["down"],
+        &["top"],
+    ];
+
+    t.checkout("main");
+    for args in commands {
+        let out = t.gx(args);
+        assert_eq!(out.code, 1, "{args:?}");
+        assert!(
+            out.stderr
+                .starts_with("Error: main is the trunk branch. Switch to a branch in a stack "),
+            "{args:?}: {}",
+            out.stderr
+        );
+    }
+
+    t.detach(t.tip("a"));
+    for args in commands {
+        let out = t.gx(args);
+        assert_eq!(out.code, 4, "{args:?}");
+        assert!(
+            out.stderr.contains("Switch to a branch in a stack "),
+            "{args:?}: {}",
+            out.stderr
+        );
+    }
+}
+
+#[test]
+fn failures_exit_with_their_documented_codes() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let out = std::process::Command::new(env!("CARGO_BIN_EXE_gx"))
+        .args(["stack", "list"])
+        .current_dir(dir.path())
+        .env("GIT_CEILING_DIRECTORIES", dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr),
+        "Error: Not a git repository.\n"
+    );
+
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    t.detach(t.tip("a"));
+    let out = t.gx(&["stack", "restack"]);
+    assert_eq!(out.code, 4);
+    assert!(out.stdout.is_empty());
+    assert!(out
+        .stderr
+        .starts_with("Error: HEAD is not currently pointing to a local branch."));
+
+    // A commit no branch points at or past is not part of any stack.
+    t.commit("loose");
+    for args in [&["stack", "list"][..], &["stack", "list", "--json"]] {
+        let out = t.gx(args);
+        assert_eq!(out.code, 1);
+        assert!(out.stdout.is_empty());
+        assert!(out.stderr.contains("not part of a stack"), "{}", out.stderr);
+    }
+
+    t.checkout("a");
+    assert_eq!(t.gx(&["stack", "continue"]).code, 1);
+}
+
+#[test]
+fn modify_amends_and_restacks_the_branches_above() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &[]);
+    t.commit_file("a1", "file", "a");
+    create(&mut t, "b", &["b1"]);
+    create(&mut t, "c", &["c1"]);
+    let a_before = t.tip("a");
+    t.checkout("a");
+
+    std::fs::write(t.path().join("file"), "changed\n").unwrap();
+    let out = t.gx_ok(&["stack", "modify", "-a"]);
+    assert!(out.contains("Amended a"), "{out}");
+    assert!(out.contains("Restacked b"), "{out}");
+    assert!(out.contains("Restacked c"), "{out}");
+
+    let a = t.repo.find_commit(t.tip("a")).unwrap();
+    assert_ne!(a.id(), a_before);
+    assert_eq!(a.summary(), Some("a1"));
+    assert_eq!(a.parent_id(0).unwrap(), t.tip("main"));
+    let b = t.repo.find_commit(t.tip("b")).unwrap();
+    assert_eq!(b.parent_id(0).unwrap(), a.id());
+    let c = t.repo.find_commit(t.tip("c")).unwrap();
+    assert_eq!(c.parent_id(0).unwrap(), b.id());
+    assert_eq!(t.head_branch(), "a");
+}
+
+#[test]
+fn modify_commit_adds_a_new_commit() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+    t.checkout("a");
+    let a_before = t.tip("a");
+
+    std::fs::write(t.path().join("new"), "new\n").unwrap();
+    let mut index = t.repo.index().unwrap();
+    index.add_path(std::path::Path::new("new")).unwrap();
+    index.write().unwrap();
+    t.gx_ok(&["stack", "modify", "--commit", "-m", "a2"]);
+
+    let a = t.repo.find_commit(t.tip("a")).unwrap();
+    assert_eq!(a.summary(), Some("a2"));
+    assert_eq!(a.parent_id(0).unwrap(), a_before);
+    let b = t.repo.find_commit(t.tip("b")).unwrap();
+    assert_eq!(b.parent_id(0).unwrap(), a.id());
+}
+
+#[test]
+fn modify_refuses_to_amend_the_parent() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &[]);
+    std::fs::write(t.path().join("a1"), "changed\n").unwrap();
+
+    let out = t.gx(&["stack", "modify", "-a"]);
+    assert_eq!(out.code, 1);
+    assert!(out.stderr.contains("--commit"), "{}", out.stderr);
+
+    // The refused -a must not have staged anything.
+    let head = t.repo.find_commit(t.tip("b")).unwrap().tree().unwrap();
+    let staged = t.repo.diff_tree_to_index(Some(&head), None, None).unwrap();
+    assert_eq!(staged.deltas().len(), 0);
+}
+
+#[test]
+fn absorb_amends_the_commits_that_introduced_the_lines() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &[]);
+    t.commit_file("a1", "file", "a1\na2");
+    create(&mut t, "b", &[]);
+    t.commit_file("b1", "file", "a1\na2\nb1\nb2");
+    create(&mut t, "c", &["c1"]);
+    t.checkout("b");
+
+    stage(&t, "file", "a1\nA2\nb1\nB2\n");
+    let out = t.gx_ok(&["absorb"]);
+    assert!(out.contains("Absorbed 1 hunk into"), "{out}");
+    assert!(out.contains("Restacked c"), "{out}");
+
+    assert_eq!(file_at(&t, "a", "file"), "a1\nA2\n");
+    assert_eq!(file_at(&t, "b", "file"), "a1\nA2\nb1\nB2\n");
+    let a = t.repo.find_commit(t.tip("a")).unwrap();
+    assert_eq!(a.summary(), Some("a1"));
+    let b = t.repo.find_commit(t.tip("b")).unwrap();
+    assert_eq!(b.parent_id(0).unwrap(), a.id());
+    let c = t.repo.find_commit(t.tip("c")).unwrap();
+    assert_eq!(c.parent_id(0).unwrap(), b.id());
+    assert_eq!(t.head_branch(), "b");
+    assert!(t.repo.statuses(None).unwrap().is_empty());
+}
+
+#[test]
+fn absorb_records_the_new_base_without_branches_above() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &[]);
+    t.commit_file("a1", "file", "a1\na2");
+    create(&mut t, "b", &["b1"]);
+
+    stage(&t, "file", "a1\nA2\n");
+    let out = t.gx_ok(&["absorb"]);
+    assert!(out.contains("Absorbed 1 hunk into"), "{out}");
+
+    let a = t.tip("a");
+    let b = t.repo.find_commit(t.tip("b")).unwrap();
+    assert_eq!(b.parent_id(0).unwrap(), a);
+    assert_eq!(config(&t, "branch.b.gx-base"), a.to_string());
+    assert_eq!(config(&t, "branch.b.gx-parent"), "a");
+    let out = t.gx_ok(&["stack", "list"]);
+    assert!(!out.contains("needs restack"), "{out}");
+}
+
+#[test]
+fn absorb_restacks_the_siblings_of_rewritten_branches() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &[]);
+    t.commit_file("a1", "file", "a1\na2");
+    create(&mut t, "s", &["s1"]);
+    create(&mut t, "s2", &["s2"]);
+    t.checkout("a");
+    create(&mut t, "b", &["b1"]);
+
+    stage(&t, "file", "a1\nA2\n");
+    let out = t.gx_ok(&["absorb"]);
+    assert!(out.contains("Restacked s"), "{out}");
+    assert!(out.contains("Restacked s2"), "{out}");
+
+    let s = t.repo.find_commit(t.tip("s")).unwrap();
+    assert_eq!(s.parent_id(0).unwrap(), t.tip("a"));
+    let s2 = t.repo.find_commit(t.tip("s2")).unwrap();
+    assert_eq!(s2.parent_id(0).unwrap(), s.id());
+    assert_eq!(file_at(&t, "s2", "file"), "a1\nA2\n");
+    assert_eq!(t.head_branch(), "b");
+}
+
+#[test]
+fn absorb_leaves_hunks_outside_the_stack_staged() {
+    let mut t = TestRepo::new();
+    t.commit_file("base", "file", "base\nmid");
+    create(&mut t, "a", &[]);
+    t.commit_file("a1", "file", "base\nmid\na1");
+
+    stage(&t, "file", "BASE\nmid\na1!\n");
+    let out = t.gx_ok(&["absorb"]);
+    assert!(out.contains("Left staged file:1"), "{out}");
+    assert_eq!(file_at(&t, "a", "file"), "base\nmid\na1!\n");
+
+    let head = t.repo.head().unwrap().peel_to_tree().unwrap();
+    let staged = t
+        .repo
+        .diff_tree_to_index(Some(&head), None, None)
+        .unwrap()
+        .stats()
+        .unwrap();
+    assert_eq!((staged.insertions(), staged.deletions()), (1, 1));
+}
+
+#[test]
+fn split_by_commit_stacks_the_new_branches_below() {
+    let mut t = TestRepo::new();
+    create(&mut t, "big", &["c1", "c2", "c3"]);
+    create(&mut t, "above", &["x1"]);
+    t.checkout("big");
+    let tip = t.tip("big");
+    let c1 = t.repo.revparse_single("big~2").unwrap().id();
+    let c2 = t.repo.revparse_single("big~1").unwrap().id();
+
+    let out = t.gx_ok(&[
+        "stack",
+        "split",
+        "--commit",
+        "big~1=two",
+        "--commit",
+        "big~2=one",
+    ]);
+    assert!(out.contains("Split big into one, two, big."), "{out}");
+
+    assert_eq!((t.tip("one"), t.tip("two"), t.tip("big")), (c1, c2, tip));
+    let one = gx::meta::read(&t.repo, "one").unwrap().unwrap();
+    assert_eq!((one.parent.as_str(), one.base), ("main", t.tip("main")));
+    let two = gx::meta::read(&t.repo, "two").unwrap().unwrap();
+    assert_eq!((two.parent.as_str(), two.base), ("one", c1));
+    let big = gx::meta::read(&t.repo, "big").unwrap().unwrap();
+    assert_eq!((big.parent.as_str(), big.base), ("two", c2));
+    assert_eq!(
+        gx::meta::read(&t.repo, "above").unwrap().unwrap().parent,
+        "big"
+    );
+    assert_eq!(t.head_branch(), "big");
+}
+
+#[test]
+fn split_replaces_the_branch_when_nothing_is_left() {
+    let mut t = TestRepo::new();
+    create(&mut t, "big", &["c1", "c2"]);
+    create(&mut t, "above", &["x1"]);
+    t.checkout("big");
+
+    t.gx_ok(&[
+        "stack",
+        "split",
+        "--commit",
+        "big~1=one",
+        "--commit",
+        "big=two",
+    ]);
+
+    assert!(t.repo.find_branch("big", git2::BranchType::Local).is_err());
+    assert_eq!(t.head_branch(), "two");
+    assert_eq!(
+        gx::meta::read(&t.repo, "above").unwrap().unwrap().parent,
+        "two"
+    );
+    let above = t.repo.find_commit(t.tip("above")).unwrap();
+    assert_eq!(above.parent_id(0).unwrap(), t.tip("two"));
+}
+
+#[test]
+fn split_by_file_moves_changes_down_and_restacks() {
+    let mut t = TestRepo::new();
+    create(&mut t, "big", &[]);
+    t.commit_file("docs", "README", "docs");
+    t.commit_file("code", "main.rs", "code");
+    t.commit_file("more docs", "README", "more docs");
+    create(&mut t, "above", &["x1"]);
+    t.checkout("big");
+
+    let out = t.gx_ok(&["stack", "split", "--file", "READ*=docs"]);
+    assert!(out.contains("Restacked above"), "{out}");
+
+    assert_eq!(file_at(&t, "docs", "README"), "more docs\n");
+    let docs = t.repo.find_commit(t.tip("docs")).unwrap();
+    assert_eq!(docs.summary(), Some("docs"));
+    assert_eq!(docs.parent_id(0).unwrap(), t.tip("main"));
+
+    // Only the commit touching main.rs is left on big.
+    let big = t.repo.find_commit(t.tip("big")).unwrap();
+    assert_eq!(big.summary(), Some("code"));
+    assert_eq!(big.parent_id(0).unwrap(), docs.id());
+    assert_eq!(file_at(&t, "big", "README"), "more docs\n");
+    let above = t.repo.find_commit(t.tip("above")).unwrap();
+    assert_eq!(above.parent_id(0).unwrap(), big.id());
+    assert!(t.repo.statuses(None).unwrap().is_empty());
+}
+
+#[test]
+fn split_by_file_ignores_changes_below_a_stale_base() {
+    let mut t = TestRepo::new();
+    let init = t.tip("main");
+    t.commit_file("trunk", "trunk.txt", "trunk");
+    create(&mut t, "big", &[]);
+    t.commit_file("docs", "README", "docs");
+    t.commit_file("code", "main.rs", "code");
+    // As if big had been rebased onto the newer trunk outside gx.
+    t.repo
+        .config()
+        .unwrap()
+        .set_str("branch.big.gx-base", &init.to_string())
+        .unwrap();
+
+    let out = t.gx(&["stack", "split", "--file", "trunk.txt=trunk"]);
+    assert_eq!(out.code, 1);
+    assert!(
+        out.stderr.contains("No changes on big match trunk.txt"),
+        "{}",
+        out.stderr
+    );
+
+    t.gx_ok(&["stack", "split", "--file", "README=docs"]);
+    let docs = t.repo.find_commit(t.tip("docs")).unwrap();
+    assert_eq!(docs.parent_id(0).unwrap(), t.tip("main"));
+    assert_eq!(config(&t, "branch.docs.gx-base"), t.tip("main").to_string());
+    let big = t.repo.find_commit(t.tip("big")).unwrap();
+    assert_eq!(big.summary(), Some("code"));
+    assert_eq!(big.parent_id(0).unwrap(), docs.id());
+}
+
+#[test]
+fn fold_moves_the_commits_onto_the_parent() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1", "b2"]);
+    create(&mut t, "c", &["c1"]);
+    t.checkout("a");
+    create(&mut t, "s", &["s1"]);
+    t.checkout("b");
+    let (b, c) = (t.tip("b"), t.tip("c"));
+
+    let out = t.gx_ok(&["stack", "fold"]);
+    assert!(out.contains("Folded b into a"), "{out}");
+    assert!(out.contains("Restacked s"), "{out}");
+
+    assert_eq!(t.tip("a"), b);
+    assert!(t.repo.find_branch("b", git2::BranchType::Local).is_err());
+    assert_eq!(t.tip("c"), c);
+    assert_eq!(gx::meta::read(&t.repo, "c").unwrap().unwrap().parent, "a");
+    let s = t.repo.find_commit(t.tip("s")).unwrap();
+    assert_eq!(s.parent_id(0).unwrap(), b);
+    assert_eq!(t.head_branch(), "a");
+}
+
+#[test]
+fn fold_squash_adds_a_single_commit() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1", "b2"]);
+    create(&mut t, "c", &["c1"]);
+    t.checkout("b");
+    let a_before = t.tip("a");
+
+    t.gx_ok(&["stack", "fold", "--squash"]);
+
+    let a = t.repo.find_commit(t.tip("a")).unwrap();
+    assert_eq!(a.parent_id(0).unwrap(), a_before);
+    assert_eq!(a.message(), Some("b1\n\nb2"));
+    assert_eq!(file_at(&t, "a", "b2"), "b2\n");
+    let c = t.repo.find_commit(t.tip("c")).unwrap();
+    assert_eq!(c.parent_id(0).unwrap(), a.id());
+}
+
+#[test]
+fn fold_abort_brings_back_the_folded_branch() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &[]);
+    t.commit_file("b1", "f", "b");
+    t.checkout("a");
+    create(&mut t, "s", &[]);
+    t.commit_file("s1", "f", "s");
+    t.checkout("b");
+    let (a, b, s) = (t.tip("a"), t.tip("b"), t.tip("s"));
+
+    let out = t.gx(&["stack", "fold", "--squash"]);
+    assert_eq!(out.code, 6, "{}{}", out.stdout, out.stderr);
+    assert_ne!(t.tip("a"), a);
+
+    let out = t.gx_ok(&["stack", "abort"]);
+    assert!(out.contains("All branches were restored."), "{out}");
+    assert_eq!((t.tip("a"), t.tip("b"), t.tip("s")), (a, b, s));
+    assert_eq!(config(&t, "branch.b.gx-parent"), "a");
+    assert_eq!(config(&t, "branch.b.gx-base"), a.to_string());
+    assert_eq!(t.head_branch(), "a");
+}
+
+#[test]
+fn fold_refuses_the_bottom_branch() {
+    let mut t = TestRepo::new();
+    create(&mut t, "a", &["a1"]);
+
+    let out = t.gx(&["stack", "fold"]);
+    assert_eq!(out.code, 1);
+    assert!(
+        out.stderr.contains("directly on the trunk"),
+        "{}",
+        out.stderr
+    );
+}
+
+/// Value of `key` in the repository's configuration.
+fn config(t: &TestRepo, key: &str) -> String {
+    t.repo.config().unwrap().get_string(key).unwrap()
+}
+
+#[test]
+fn push_creates_and_updates_the_branches_on_the_remote() {
+    let mut t = TestRepo::new();
+    t.add_remote();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+
+    let out = t.gx_ok(&["stack", "push"]);
+    assert_eq!(
+        lines(&out),
+        ["created    a -> origin/a", "created    b -> origin/b"]
+    );
+    assert_eq!(t.remote_tip("a"), Some(t.tip("a")));
+    assert_eq!(t.remote_tip("b"), Some(t.tip("b")));
+    assert_eq!(config(&t, "branch.b.merge"), "refs/heads/b");
+
+    t.commit("b2");
+    let out = t.gx_ok(&["stack", "push"]);
+    assert_eq!(lines(&out), ["unchanged  a", "updated    b -> origin/b"]);
+    assert_eq!(t.remote_tip("b"), Some(t.tip("b")));
+}
+
+#[test]
+fn push_refuses_to_overwrite_changes_it_has_not_fetched() {
+    let mut t = TestRepo::new();
+    t.add_remote();
+    create(&mut t, "a", &["a1"]);
+    t.gx_ok(&["stack", "push"]);
+    let theirs = t.squash(t.tip("a"), t.tip("a"), "someone else");
+    t.push_to_remote(theirs, "a");
+    t.commit("a2");
+
+    let out = t.gx(&["stack", "push"]);
+    assert_eq!(out.code, 1);
+    assert_eq!(
+        lines(&out.stdout),
+        ["rejected   a (remote has changed since the last fetch)"]
+    );
+    assert_eq!(t.remote_tip("a"), Some(theirs));
+}
+
+#[test]
+fn sync_deletes_squash_merged_branches_and_restacks_the_rest() {
+    let mut t = TestRepo::new();
+    t.add_remote();
+    create(&mut t, "a", &["a1", "a2"]);
+    create(&mut t, "b", &["b1"]);
+    let squashed = t.squash(t.tip("main"), t.tip("a"), "a (#1)");
+    t.push_to_remote(squashed, "main");
+
+    let out = t.gx_ok(&["stack", "sync", "--force"]);
+    assert!(out.contains(&format!("Fast-forwarded main to {}.", short(squashed))));
+    assert!(out.contains("These branches have been merged into main: a"));
+    assert!(out.contains("Deleted merged branch a."));
+    assert_eq!(t.tip("main"), squashed);
+    assert!(t.repo.find_branch("a", BranchType::Local).is_err());
+
+    let b = t.repo.find_commit(t.tip("b")).unwrap();
+    assert_eq!(b.summary(), Some("b1"));
+    assert_eq!(b.parent_id(0).unwrap(), squashed);
+    assert_eq!(config(&t, "branch.b.gx-parent"), "main");
+    assert_eq!(t.head_branch(), "b");
+}
+
+#[test]
+fn sync_keeps_merged_branches_as_they_are_when_deletion_is_declined() {
+    let mut t = TestRepo::new();
+    t.add_remote();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+    let a_before = t.tip("a");
+    let squashed = t.squash(t.tip("main"), a_before, "a (#1)");
+    t.push_to_remote(squashed, "main");
+
+    // stdin is empty, which answers no.
+    let out = t.gx_ok(&["stack", "sync"]);
+    assert!(out.contains("These branches have been merged into main: a"));
+    assert!(!out.contains("Restacked a"), "{out}");
+    assert!(out.contains("Restacked b"), "{out}");
+    assert_eq!(t.tip("a"), a_before);
+    let b = t.repo.find_commit(t.tip("b")).unwrap();
+    assert_eq!(b.parent_id(0).unwrap(), squashed);
+    assert_eq!(config(&t, "branch.b.gx-parent"), "main");
+
+    t.checkout("a");
+    let out = t.gx_ok(&["stack", "sync", "--force"]);
+    assert!(out.contains("Deleted merged branch a."), "{out}");
+}
+
+#[test]
+fn submit_opens_a_pull_request_per_branch() {
+    let github = MockGitHub::start();
+    let mut t = TestRepo::new();
+    t.add_remote();
+    t.repo
+        .config()
+        .unwrap()
+        .set_str("gx.githubRepo", "o/r")
+        .unwrap();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+    let env = [
+        ("GITHUB_TOKEN", "test-token"),
+        ("GX_GITHUB_API_URL", github.url.as_str()),
+    ];
+
+    let out = t.gx_with_env(&["stack", "submit"], &env);
+    assert_eq!(out.code, 0, "{}{}", out.stdout, out.stderr);
+    assert_eq!(
+        lines(&out.stdout),
+        [
+            "created    a -> origin/a",
+            "created    b -> origin/b",
+            "created    a #1 (main <- a) https://github.com/o/r/pull/1",
+            "created    b #2 (a <- b) https://github.com/o/r/pull/2",
+            "Updated the stack section of 2 pull request description(s).",
+        ]
+    );
+    assert_eq!(github.pr(2)["base"]["ref"], "a");
+    assert!(github.pr(2)["body"]
+        .as_str()
+        .unwrap()
+        .contains("- #2 \u{1f448}\n- #1\n- `main`"));
+    assert_eq!(config(&t, "branch.b.gx-pr"), "2");
+
+    let out = t.gx_with_env(&["stack", "submit"], &env);
+    assert_eq!(out.code, 0, "{}{}", out.stdout, out.stderr);
+    assert_eq!(
+        lines(&out.stdout),
+        [
+            "unchanged  a",
+            "unchanged  b",
+            "unchanged  a #1 (main <- a) https://github.com/o/r/pull/1",
+            "unchanged  b #2 (a <- b) https://github.com/o/r/pull/2",
+        ]
+    );
+    assert_eq!(github.count(), 2);
+}
+
+#[test]
+fn stacks_show_the_state_of_each_pull_request() {
+    let github = MockGitHub::start();
+    let mut t = TestRepo::new();
+    t.add_remote();
+    t.repo
+        .config()
+        .unwrap()
+        .set_str("gx.githubRepo", "o/r")
+        .unwrap();
+    create(&mut t, "a", &["a1"]);
+    create(&mut t, "b", &["b1"]);
+    let env = [
+        ("GITHUB_TOKEN", "test-token"),
+        ("GX_GITHUB_API_URL", github.url.as_str()),
+    ];
+    let out = t.gx_with_env(&["stack", "submit"], &env);
+    assert_eq!(out.code, 0, "{}{}", out.stdout, out.stderr);
+    assert!(t.gx_ok(&["stacks"]).contains(", PRs #1 open, #2 open)"));
+
+    github.update(1, |pr| {
+        pr["state"] = "closed".into();
+        pr["merged_at"] = "2024-05-01T12:00:00Z".into();
+    });
+    github.update(2, |pr| pr["draft"] = true.into());
+    let out = t.gx_with_env(&["stacks"], &env);
+    assert_eq!(out.code, 0, "{}{}", out.stdout, out.stderr);
+    assert!(
+        out.stdout.contains(", PRs #1 merged, #2 draft)"),
+        "{}",
+        out.stdout
+    );
+
+    // Without a token the states fetched last time are shown.
+    let out = t.gx_ok(&["stacks"]);
+    assert!(out.contains(", PRs #1 merged, #2 draft)"), "{out}");
+}
@@ -0,0 +1,429 @@
+//! Builds throwaway repositories for the integration tests and runs the `gx`
+//! binary inside them.
+
+#![allow(dead_code)]
+
+use git2::{BranchType, Oid, Repository, Signature, Time};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::{fs, path::Path, thread};
+use tempfile::TempDir;
+
+/// Commit times start here and go up by a minute per commit, so output with
+/// `--date iso` is stable.
+const EPOCH: i64 = 1_718_900_000;
+
+pub struct TestRepo {
+    pub dir: TempDir,
+    pub repo: Repository,
+    commits: i64,
+    /// Bare repository behind `origin`, once [`TestRepo::add_remote`] ran.
+    remote: Option<TempDir>,
+}
+
+/// What a `gx` invocation printed and how it exited.
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub code: i32,
+}
+
+impl TestRepo {
+    /// A repository on `main` with a single commit.
+    pub fn new() -> TestRepo {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        {
+            let mut config = repo.config().unwrap();
+            config.set_str("user.name", "A U Thor").unwrap();
+            config.set_str("user.email", "author@example.com").unwrap();
+        }
+        repo.set_head("refs/heads/main").unwrap();
+        let mut test_repo = TestRepo {
+            dir,
+            repo,
+            commits: 0,
+            remote: None,
+        };
+        test_repo.commit("init");
+        test_repo
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Commits a new file named after `message` on the checked out branch.
+    pub fn commit(&mut self, message: &str) -> Oid {
+        let file = message.replace(' ', "-");
+        self.commit_file(message, &file, message)
+    }
+
+    /// Commits `contents` to `file` on the checked out branch.
+    pub fn commit_file(&mut self, message: &str, file: &str, contents: &str) -> Oid {
+        let signature = self.signature();
+        fs::write(self.path().join(file), format!("{contents}\n")).unwrap();
+        let mut index = self.repo.index().unwrap();
+        index.add_path(Path::new(file)).unwrap();
+        index.write().unwrap();
+        let tree = self.repo.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let parents = match self.repo.head() {
+            Ok(head) => vec![head.peel_to_commit().unwrap()],
+            Err(_) => Vec::new(),
+        };
+        let parents: Vec<_> = parents.iter().collect();
+        self.repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents,
+            )
+            .unwrap()
+    }
+
+    /// Merges branch `other` into the checked out branch with a merge commit.
+    /// The branches must not touch the same files.
+    pub fn merge(&mut self, other: &str) -> Oid {
+        let signature = self.signature();
+        let head = self.repo.head().unwrap().peel_to_commit().unwrap();
+        let theirs = self.tip(other);
+        let theirs = self.repo.find_commit(theirs).unwrap();
+        let mut index = self.repo.merge_commits(&head, &theirs, None).unwrap();
+        assert!(!index.has_conflicts());
+        let tree = self
+            .repo
+            .find_tree(index.write_tree_to(&self.repo).unwrap())
+            .unwrap();
+
+        let oid = self
+            .repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                &format!("Merge branch '{other}'"),
+                &tree,
+                &[&head, &theirs],
+            )
+            .unwrap();
+        self.repo
+            .checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        oid
+    }
+
+    /// Creates branch `name` at HEAD and checks it out, without recording any
+    /// gx metadata.
+    pub fn branch(&self, name: &str) {
+        let head = self.repo.head().unwrap().peel_to_commit().unwrap();
+        self.repo.branch(name, &head, false).unwrap();
+        self.checkout(name);
+    }
+
+    pub fn checkout(&self, name: &str) {
+        let refname = format!("refs/heads/{name}");
+        let tree = self.repo.revparse_single(&refname).unwrap();
+        self.repo
+            .checkout_tree(&tree, Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        self.repo.set_head(&refname).unwrap();
+    }
+
+    pub fn detach(&self, oid: Oid) {
+        let commit = self.repo.find_commit(oid).unwrap();
+        self.repo
+            .checkout_tree(
+                commit.as_object(),
+                Some(git2::build::CheckoutBuilder::new().force()),
+            )
+            .unwrap();
+        self.repo.set_head_detached(oid).unwrap();
+    }
+
+    /// Commits the files of `tip` on top of `onto` without moving any branch,
+    /// the way a pull request gets squash-merged.
+    pub fn squash(&mut self, onto: Oid, tip: Oid, message: &str) -> Oid {
+        let signature = self.signature();
+        let tree = self.repo.find_commit(tip).unwrap().tree().unwrap();
+        let parent = self.repo.find_commit(onto).unwrap();
+        self.repo
+            .commit(None, &signature, &signature, message, &tree, &[&parent])
+            .unwrap()
+    }
+
+    /// Adds a bare repository as `origin`, pushes `main` to it and makes
+    /// `origin/main` the upstream of `main`.
+    pub fn add_remote(&mut self) {
+        let dir = TempDir::new().unwrap();
+        Repository::init_bare(dir.path()).unwrap();
+        let mut remote = self
+            .repo
+            .remote("origin", dir.path().to_str().unwrap())
+            .unwrap();
+        remote
+            .push(&["refs/heads/main:refs/heads/main"], None)
+            .unwrap();
+        remote.fetch(&[] as &[&str], None, None).unwrap();
+        self.repo
+            .find_branch("main", BranchType::Local)
+            .unwrap()
+            .set_upstream(Some("origin/main"))
+            .unwrap();
+        self.remote = Some(dir);
+    }
+
+    /// The bare repository behind `origin`.
+    pub fn remote(&self) -> Repository {
+        Repository::open_bare(self.remote.as_ref().unwrap().path()).unwrap()
+    }
+
+    /// Points `branch` on `origin` at `oid`, as if someone else had pushed
+    /// it. The remote-tracking branches only notice on the next fetch.
+    pub fn push_to_remote(&self, oid: Oid, branch: &str) {
+        let scratch = "refs/test/push";
+        self.repo.reference(scratch, oid, true, "test").unwrap();
+        self.repo
+            .find_remote("origin")
+            .unwrap()
+            .push(&[format!("+{scratch}:{scratch}").as_str()], None)
+            .unwrap();
+        self.repo.find_reference(scratch).unwrap().delete().unwrap();
+        let remote = self.remote();
+        remote
+            .reference(&format!("refs/heads/{branch}"), oid, true, "test")
+            .unwrap();
+        remote.find_reference(scratch).unwrap().delete().unwrap();
+    }
+
+    /// Where `branch` points on `origin`, if it exists there.
+    pub fn remote_tip(&self, branch: &str) -> Option<Oid> {
+        self.remote()
+            .refname_to_id(&format!("refs/heads/{branch}"))
+            .ok()
+    }
+
+    pub fn tip(&self, name: &str) -> Oid {
+        self.repo
+            .find_branch(name, BranchType::Local)
+            .unwrap()
+            .get()
+            .peel_to_commit()
+            .unwrap()
+            .id()
+    }
+
+    pub fn head_branch(&self) -> String {
+        self.repo.head().unwrap().shorthand().unwrap().to_string()
+    }
+
+    /// Runs `gx` with `args` in the repository, with colors disabled and no
+    /// global git configuration.
+    pub fn gx(&self, args: &[&str]) -> Output {
+        self.gx_with_env(args, &[])
+    }
+
+    /// Runs `gx` like [`TestRepo::gx`], with the extra environment `vars`.
+    pub fn gx_with_env(&self, args: &[&str], vars: &[(&str, &str)]) -> Output {
+        let output = self
+            .command(args)
+            .envs(vars.iter().copied())
+            .output()
+            .unwrap();
+        Output {
+            stdout: String::from_utf8(output.stdout).unwrap(),
+            stderr: String::from_utf8(output.stderr).unwrap(),
+            code: output.status.code().unwrap_or(-1),
+        }
+    }
+
+    /// Runs `gx` and fails the test unless it succeeds. Returns its stdout.
+    pub fn gx_ok(&self, args: &[&str]) -> String {
+        let output = self.gx(args);
+        assert_eq!(
+            output.code, 0,
+            "gx {args:?} failed:\n{}{}",
+            output.stdout, output.stderr
+        );
+        output.stdout
+    }
+
+    /// Runs `gx` with its stdout closed before it writes anything, like a
+    /// pager that quit early. Returns the exit code and stderr.
+    pub fn gx_into_closed_pipe(&self, args: &[&str]) -> (i32, String) {
+        let mut child = self
+            .command(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        drop(child.stdout.take());
+        let output = child.wait_with_output().unwrap();
+        (
+            output.status.code().unwrap_or(-1),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_gx"));
+        command
+            .args(args)
+            .current_dir(self.path())
+            .env("NO_COLOR", "1")
+            .env("CLICOLOR", "0")
+            .env("HOME", self.path())
+            .env("XDG_CONFIG_HOME", self.path())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env_remove("GITHUB_TOKEN")
+            .env_remove("GH_TOKEN")
+            .env_remove("GX_GITHUB_API_URL");
+        command
+    }
+
+    fn signature(&mut self) -> Signature<'static> {
+        let time = Time::new(EPOCH + self.commits * 60, 120);
+        self.commits += 1;
+        Signature::new("A U Thor", "author@example.com", &time).unwrap()
+    }
+}
+
+/// First seven characters of `oid`, as gx prints it.
+pub fn short(oid: Oid) -> String {
+    oid.to_string()[..7].to_string()
+}
+
+/// A stand-in for the pull request endpoints of the GitHub API, serving one
+/// repository from a background thread.
+pub struct MockGitHub {
+    pub url: String,
+    prs: Arc<Mutex<Vec<Value>>>,
+}
+
+impl MockGitHub {
+    pub fn start() -> MockGitHub {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let prs = Arc::new(Mutex::new(Vec::new()));
+        let served = Arc::clone(&prs);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                serve(stream.unwrap(), &served);
+            }
+        });
+        MockGitHub { url, prs }
+    }
+
+    /// Pull request `number`, as the API would return it.
+    pub fn pr(&self, number: u64) -> Value {
+        self.prs.lock().unwrap()[number as usize - 1].clone()
+    }
+
+    pub fn count(&self) -> usize {
+        self.prs.lock().unwrap().len()
+    }
+
+    /// Changes pull request `number` as if someone had acted on it on GitHub.
+    pub fn update(&self, number: u64, change: impl FnOnce(&mut Value)) {
+        change(&mut self.prs.lock().unwrap()[number as usize - 1]);
+    }
+}
+
+/// Answers a single request and closes the connection.
+fn serve(stream: TcpStream, prs: &Mutex<Vec<Value>>) {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut length = 0;
+    let mut authorized = false;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':').unwrap();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => length = value.trim().parse().unwrap(),
+            "authorization" => authorized = value.trim() == "Bearer test-token",
+            _ => {}
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let mut prs = prs.lock().unwrap();
+    let number = path
+        .strip_prefix("/repos/o/r/pulls/")
+        .and_then(|n| n.parse::<usize>().ok())
+        .filter(|n| (1..=prs.len()).contains(n));
+    let (status, response) = match (authorized, method.as_str(), path, number) {
+        (false, ..) => (401, json!({ "message": "Bad credentials" })),
+        (_, "GET", "/repos/o/r/pulls", _) => {
+            let head = query
+                .split('&')
+                .find_map(|p| p.strip_prefix("head="))
+                .unwrap_or_default()
+                .replace("%3A", ":");
+            let open: Vec<Value> = prs
+                .iter()
+                .filter(|pr| {
+                    pr["state"] == "open"
+                        && format!("o:{}", pr["head"]["ref"].as_str().unwrap()) == head
+                })
+                .cloned()
+                .collect();
+            (200, Value::Array(open))
+        }
+        (_, "POST", "/repos/o/r/pulls", _) => {
+            let number = prs.len() + 1;
+            let pr = json!({
+                "number": number,
+                "html_url": format!("https://github.com/o/r/pull/{number}"),
+                "state": "open",
+                "draft": false,
+                "merged_at": null,
+                "body": body["body"],
+                "base": { "ref": body["base"] },
+                "head": { "ref": body["head"] },
+                "title": body["title"],
+            });
+            prs.push(pr.clone());
+            (201, pr)
+        }
+        (_, "GET", _, Some(n)) => (200, prs[n - 1].clone()),
+        (_, "PATCH", _, Some(n)) => {
+            let pr = &mut prs[n - 1];
+            if let Some(base) = body.get("base") {
+                pr["base"]["ref"] = base.clone();
+            }
+            if let Some(text) = body.get("body") {
+                pr["body"] = text.clone();
+            }
+            (200, pr.clone())
+        }
+        _ => (404, json!({ "message": "Not Found" })),
+    };
+    drop(prs);
+
+    let response = response.to_string();
+    write!(
+        &stream,
+        "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+        response.len()
+    )
+    .unwrap();
+}
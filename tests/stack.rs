@@ -0,0 +1,282 @@
+mod common;
+
+use common::TestRepo;
+use gx::restack::{self, Outcome};
+use gx::stack::{self, Stack};
+use gx::{branch, state, status, trunk};
+
+/// Creates branch `name` on top of the checked out branch the way
+/// `gx stack create` does, and commits `commits` to it.
+fn stack_branch(t: &mut TestRepo, name: &str, commits: &[&str]) {
+    let trunk = trunk::find_trunk(&t.repo, None).unwrap();
+    let parent = t.head_branch();
+    branch::create(&t.repo, &trunk, &parent, name, None).unwrap();
+    for message in commits {
+        t.commit(message);
+    }
+}
+
+fn stack_of(t: &TestRepo, name: &str) -> Stack {
+    let trunk = trunk::find_trunk(&t.repo, None).unwrap();
+    Stack::for_branch(&t.repo, trunk, name).unwrap().unwrap()
+}
+
+fn names(stack: &Stack) -> Vec<&str> {
+    stack.branches.iter().map(|b| b.name.as_str()).collect()
+}
+
+#[test]
+fn linear_stack_is_ordered_parents_first() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1", "a2"]);
+    stack_branch(&mut t, "b", &["b1"]);
+    stack_branch(&mut t, "c", &["c1"]);
+
+    let stack = stack_of(&t, "b");
+    assert_eq!(names(&stack), ["a", "b", "c"]);
+    let a = stack.find("a").unwrap();
+    let b = stack.find("b").unwrap();
+    assert_eq!(a.parent, "main");
+    assert_eq!(b.parent, "a");
+    assert_eq!(b.base, a.tip);
+    assert!(b.tracked);
+    assert_eq!(stack.commits(&t.repo, a).unwrap().len(), 2);
+    assert_eq!(stack.commits(&t.repo, b).unwrap(), [b.tip]);
+}
+
+#[test]
+fn untracked_branches_are_inferred_from_history() {
+    let mut t = TestRepo::new();
+    t.branch("a");
+    t.commit("a1");
+    t.branch("b");
+    t.commit("b1");
+
+    let stack = stack_of(&t, "b");
+    assert_eq!(names(&stack), ["a", "b"]);
+    let b = stack.find("b").unwrap();
+    assert_eq!(b.parent, "a");
+    assert_eq!(b.base, t.tip("a"));
+    assert!(!b.tracked);
+}
+
+#[test]
+fn forks_have_several_children() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    stack_branch(&mut t, "b", &["b1"]);
+    t.checkout("a");
+    stack_branch(&mut t, "c", &["c1"]);
+
+    let stack = stack_of(&t, "c");
+    assert_eq!(names(&stack), ["a", "b", "c"]);
+    let children: Vec<&str> = stack
+        .children("a")
+        .iter()
+        .map(|b| b.name.as_str())
+        .collect();
+    assert_eq!(children, ["b", "c"]);
+    let tips: Vec<&str> = stack.tips().iter().map(|b| b.name.as_str()).collect();
+    assert_eq!(tips, ["b", "c"]);
+    assert_eq!(stack.descendants("a"), ["b", "c"]);
+    assert!(stack.descendants("b").is_empty());
+}
+
+#[test]
+fn independent_stacks_are_listed_separately() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    stack_branch(&mut t, "b", &["b1"]);
+    t.checkout("main");
+    stack_branch(&mut t, "x", &["x1"]);
+
+    let trunk = trunk::find_trunk(&t.repo, None).unwrap();
+    let stacks = Stack::all(&t.repo, trunk).unwrap();
+    let all: Vec<Vec<&str>> = stacks.iter().map(names).collect();
+    assert_eq!(all, [vec!["a", "b"], vec!["x"]]);
+}
+
+#[test]
+fn merge_commits_are_followed_on_first_parent() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    t.checkout("main");
+    t.commit("m1");
+    t.checkout("a");
+    let merge = t.merge("main");
+    t.commit("a2");
+
+    let stack = stack_of(&t, "a");
+    let a = stack.find("a").unwrap();
+    let commits = stack.commits(&t.repo, a).unwrap();
+    assert_eq!(commits.len(), 3);
+    assert_eq!(commits[1], merge);
+    assert!(!commits.contains(&t.tip("main")));
+}
+
+#[test]
+fn detached_head_is_found_in_its_stack() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    stack_branch(&mut t, "b", &[]);
+    let b1 = t.commit("b1");
+    t.commit("b2");
+    t.detach(b1);
+
+    let trunk = trunk::find_trunk(&t.repo, None).unwrap();
+    let found = stack::stacks_containing(&t.repo, &trunk, b1).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].1, "b");
+    assert_eq!(names(&found[0].0), ["a", "b"]);
+
+    let on_trunk = stack::stacks_containing(&t.repo, &trunk, t.tip("main")).unwrap();
+    assert!(on_trunk.is_empty());
+}
+
+#[test]
+fn branches_without_upstream_need_a_push() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    stack_branch(&mut t, "b", &["b1"]);
+
+    let stack = stack_of(&t, "b");
+    let b = stack.find("b").unwrap();
+    let s = status::branch_status(&t.repo, &stack, b).unwrap();
+    assert_eq!(s.upstream, None);
+    assert_eq!(s.ahead, None);
+    assert!(s.needs_push);
+    assert!(!s.upstream_gone);
+    assert!(!s.needs_restack);
+}
+
+#[test]
+fn moving_a_parent_requires_a_restack() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    stack_branch(&mut t, "b", &["b1"]);
+    t.checkout("a");
+    t.commit("a2");
+
+    let stack = stack_of(&t, "b");
+    let b = stack.find("b").unwrap();
+    assert!(
+        status::branch_status(&t.repo, &stack, b)
+            .unwrap()
+            .needs_restack
+    );
+}
+
+#[test]
+fn moving_the_trunk_requires_a_restack() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    stack_branch(&mut t, "b", &["b1"]);
+    t.checkout("main");
+    t.commit("m1");
+
+    let stack = stack_of(&t, "b");
+    let a_status = status::branch_status(&t.repo, &stack, stack.find("a").unwrap()).unwrap();
+    assert!(a_status.needs_restack);
+    let b_status = status::branch_status(&t.repo, &stack, stack.find("b").unwrap()).unwrap();
+    assert!(!b_status.needs_restack);
+}
+
+#[test]
+fn local_branches_sharing_a_commit_are_all_kept() {
+    let mut t = TestRepo::new();
+    t.branch("b");
+    let a1 = t.commit("a1");
+    t.branch("a");
+
+    let branches = stack::get_local_branches(&t.repo).unwrap();
+    assert_eq!(branches.len(), 2);
+    let at_a1: Vec<&str> = branches[&a1]
+        .iter()
+        .map(|b| b.name().unwrap().unwrap())
+        .collect();
+    assert_eq!(at_a1, ["a", "b"]);
+}
+
+#[test]
+fn new_branches_on_the_same_commit_keep_their_place() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    stack_branch(&mut t, "b", &[]);
+    stack_branch(&mut t, "c", &[]);
+
+    let stack = stack_of(&t, "a");
+    assert_eq!(names(&stack), ["a", "b", "c"]);
+    assert_eq!(stack.find("b").unwrap().parent, "a");
+    assert_eq!(stack.find("c").unwrap().parent, "b");
+    let c = stack.find("c").unwrap();
+    assert!(stack.commits(&t.repo, c).unwrap().is_empty());
+}
+
+#[test]
+fn recorded_parents_disambiguate_branches_on_the_same_commit() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    stack_branch(&mut t, "b", &[]);
+    // Untracked, so its parent has to be inferred from the shared commit.
+    t.branch("c");
+    t.commit("c1");
+    // Untracked and on the tip of tracked `b`, so it is stacked on `b`.
+    t.checkout("b");
+    t.branch("d");
+
+    let stack = stack_of(&t, "c");
+    assert_eq!(stack.find("c").unwrap().parent, "b");
+    assert_eq!(stack.find("c").unwrap().base, t.tip("a"));
+    assert_eq!(stack.find("d").unwrap().parent, "b");
+}
+
+#[test]
+fn restack_reports_what_happened_to_each_branch() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &["a1"]);
+    stack_branch(&mut t, "b", &["b1"]);
+    stack_branch(&mut t, "c", &["c1"]);
+    t.checkout("a");
+    t.commit("a2");
+    let old_b = t.tip("b");
+
+    let report = restack::restack(&t.repo, None, false).unwrap();
+    assert_eq!(report.conflict, None);
+    let [(b, moved), (c, _)] = &report.branches[..] else {
+        panic!("{:?}", report.branches);
+    };
+    assert_eq!((b.as_str(), c.as_str()), ("b", "c"));
+    assert_eq!(
+        *moved,
+        Outcome::Moved {
+            from: old_b,
+            to: t.tip("b")
+        }
+    );
+
+    let report = restack::restack(&t.repo, None, false).unwrap();
+    assert!(report
+        .branches
+        .iter()
+        .all(|(_, outcome)| *outcome == Outcome::UpToDate));
+}
+
+#[test]
+fn restack_reports_the_branch_it_stopped_on() {
+    let mut t = TestRepo::new();
+    stack_branch(&mut t, "a", &[]);
+    t.commit_file("a1", "f", "a");
+    stack_branch(&mut t, "b", &[]);
+    t.commit_file("b1", "f", "b");
+    t.checkout("a");
+    t.commit_file("a2", "f", "a2");
+
+    let report = restack::restack(&t.repo, None, false).unwrap();
+    assert!(report.branches.is_empty());
+    assert_eq!(report.conflict.as_deref(), Some("b"));
+    assert!(state::read(&t.repo).unwrap().is_some());
+
+    let op = restack::abort_operation(&t.repo).unwrap();
+    assert_eq!(op.kind, "restack");
+    assert!(state::read(&t.repo).unwrap().is_none());
+}